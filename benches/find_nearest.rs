@@ -0,0 +1,81 @@
+use divan::Bencher;
+use divan::black_box;
+use divan::counter::ItemsCount;
+use geo_coding::BatchOptions;
+use geo_coding::EuclideanDistanceSquared;
+use geo_coding::Layout;
+use geo_coding::Tree2D;
+use geo_coding::TreeOptions;
+
+fn main() {
+    divan::main();
+}
+
+const NUM_NODES: usize = 4_000_000;
+const NUM_QUERIES: usize = 10_000;
+const LAYOUTS: [Layout; 3] = [Layout::Explicit, Layout::Implicit, Layout::VanEmdeBoas];
+
+#[divan::bench(args = LAYOUTS, consts = [1, 16])]
+fn find_nearest<const LEAF_SIZE: usize>(bencher: Bencher, layout: Layout) {
+    let tree = build_tree(LEAF_SIZE, layout);
+    let queries = random_locations(NUM_QUERIES, 2);
+    bencher.counter(ItemsCount::new(NUM_QUERIES)).bench(|| {
+        for location in queries.iter() {
+            black_box(tree.find_nearest_by_metric(
+                location,
+                u64::MAX,
+                10,
+                &EuclideanDistanceSquared,
+            ));
+        }
+    });
+}
+
+#[divan::bench(args = LAYOUTS, consts = [1, 16])]
+fn find_nearest_one<const LEAF_SIZE: usize>(bencher: Bencher, layout: Layout) {
+    let tree = build_tree(LEAF_SIZE, layout);
+    let queries = random_locations(NUM_QUERIES, 2);
+    bencher.counter(ItemsCount::new(NUM_QUERIES)).bench(|| {
+        for location in queries.iter() {
+            black_box(tree.find_nearest_one(location, u64::MAX, &EuclideanDistanceSquared));
+        }
+    });
+}
+
+#[divan::bench(args = [false, true])]
+fn find_nearest_batch(bencher: Bencher, sort: bool) {
+    let tree = build_tree(16, Layout::VanEmdeBoas);
+    let queries = random_locations(NUM_QUERIES, 2);
+    let options = BatchOptions::new().sort(sort);
+    bencher.counter(ItemsCount::new(NUM_QUERIES)).bench(|| {
+        black_box(tree.find_nearest_batch(
+            &queries,
+            u64::MAX,
+            10,
+            &EuclideanDistanceSquared,
+            options,
+        ));
+    });
+}
+
+fn build_tree(leaf_size: usize, layout: Layout) -> Tree2D<i64, u32> {
+    let nodes = random_locations(NUM_NODES, 1)
+        .into_iter()
+        .zip(0..)
+        .collect();
+    let options = TreeOptions::new().leaf_size(leaf_size).layout(layout);
+    Tree2D::from_nodes_with_options(nodes, options)
+}
+
+/// Returns uniformly distributed locations; the same seed produces the same locations.
+fn random_locations(len: usize, seed: u64) -> Vec<[i64; 2]> {
+    // https://en.wikipedia.org/wiki/Xorshift
+    let mut state = seed;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % 360_000_000_000) as i64 - 180_000_000_000
+    };
+    (0..len).map(|_| [next(), next() / 2]).collect()
+}
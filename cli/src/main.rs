@@ -1,10 +1,16 @@
+use std::io::BufWriter;
+use std::io::Write;
 use std::path::PathBuf;
 use std::time::Instant;
 
 use clap::Parser;
 use clap::Subcommand;
+use clap::ValueEnum;
+use geo_coding::EarthDistance;
+use geo_coding::Layout;
 use geo_coding::Tree2D;
-use geo_coding::earth_distance;
+use geo_coding::Tree2DRef;
+use geo_coding::TreeOptions;
 use human_units::si::si_unit;
 use memmap2::Mmap;
 
@@ -25,6 +31,22 @@ enum Command {
         /// Zstd compression level.
         #[clap(long = "compression-level", default_value_t = 9)]
         compression_level: i32,
+        /// The maximum number of nodes in a leaf bucket.
+        #[clap(
+            long = "leaf-size",
+            default_value_t = 16,
+            value_parser = clap::value_parser!(u32).range(1..)
+        )]
+        leaf_size: u32,
+        /// The order in which the nodes are stored.
+        #[clap(long = "layout", value_enum, default_value_t = LayoutArg::Explicit)]
+        layout: LayoutArg,
+        /// Merge the nodes with the same name within this radius.
+        #[clap(long = "merge-radius")]
+        merge_radius: Option<Distance>,
+        /// Write uncompressed files that can be queried without decoding.
+        #[clap(long = "flat")]
+        flat: bool,
         file: PathBuf,
     },
     /// Print file contents.
@@ -46,6 +68,26 @@ enum Command {
     },
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LayoutArg {
+    /// Breadth-first order with child indices.
+    Explicit,
+    /// Eytzinger order without child indices.
+    Implicit,
+    /// Van Emde Boas order with child indices.
+    VanEmdeBoas,
+}
+
+impl From<LayoutArg> for Layout {
+    fn from(other: LayoutArg) -> Self {
+        match other {
+            LayoutArg::Explicit => Self::Explicit,
+            LayoutArg::Implicit => Self::Implicit,
+            LayoutArg::VanEmdeBoas => Self::VanEmdeBoas,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 #[si_unit(symbol = "m", min_prefix = "", max_prefix = "k")]
 pub struct Distance(pub u64);
@@ -55,22 +97,40 @@ fn main() -> anyhow::Result<()> {
     match args.command {
         Command::Convert {
             compression_level,
+            leaf_size,
+            layout,
+            merge_radius,
+            flat,
             file,
         } => {
             let t = Instant::now();
+            let options = TreeOptions::new()
+                .leaf_size(leaf_size as usize)
+                .layout(layout.into());
             let ImportedNodes {
                 other,
                 settlements,
                 countries,
-            } = ImportedNodes::import_pbf(&file)?;
+                num_merged,
+            } = ImportedNodes::import_pbf(&file, options, merge_radius.map(|r| r.0))?;
             eprintln!("Import PBF: {:?}", t.elapsed());
+            if merge_radius.is_some() {
+                eprintln!("Merged nodes: {num_merged}");
+            }
             let t = Instant::now();
-            for (tree, filename) in [
-                (other, "other.rgc.zst"),
-                (settlements, "settlements.rgc.zst"),
-                (countries, "countries.rgc.zst"),
+            for (tree, name) in [
+                (other, "other"),
+                (settlements, "settlements"),
+                (countries, "countries"),
             ] {
-                let file = fs::File::create(filename)?;
+                if flat {
+                    let file = fs::File::create(format!("{name}.rgc"))?;
+                    let mut writer = BufWriter::new(file);
+                    tree.write_flat(&mut writer)?;
+                    writer.flush()?;
+                    continue;
+                }
+                let file = fs::File::create(format!("{name}.rgc.zst"))?;
                 // TODO zstd cli compresses two times better...
                 let mut encoder = zstd::Encoder::new(file, compression_level)?;
                 tree.write(&mut encoder)?;
@@ -80,14 +140,27 @@ fn main() -> anyhow::Result<()> {
         }
         Command::Show { file } => {
             let file = fs::File::open(&file)?;
-            let file = zstd::Decoder::new(file)?;
-            let tree = Tree2D::<i64, String>::read(file)?;
-            for ([longitude, latitude], name) in tree.iter() {
+            let mmap = unsafe { Mmap::map(&file)? };
+            let print = |[longitude, latitude]: [i64; 2], name: &str| {
                 println!(
                     "{:.9} {:.9} {name}",
-                    *longitude as f64 * 1e-9,
-                    *latitude as f64 * 1e-9
+                    longitude as f64 * 1e-9,
+                    latitude as f64 * 1e-9
                 );
+            };
+            if Tree2DRef::is_flat(mmap.as_ref()) {
+                let tree = Tree2DRef::new(mmap.as_ref())?;
+                for i in 0..tree.len() {
+                    if let Some((location, name)) = tree.get(i)? {
+                        print(location, name);
+                    }
+                }
+            } else {
+                let file = zstd::Decoder::new(mmap.as_ref())?;
+                let tree = Tree2D::<i64, String>::read(file)?;
+                for (location, name) in tree.iter() {
+                    print(*location, name);
+                }
             }
         }
         Command::Find {
@@ -100,14 +173,33 @@ fn main() -> anyhow::Result<()> {
             let t = Instant::now();
             let file = fs::File::open(&file)?;
             let mmap = unsafe { Mmap::map(&file)? };
-            let file = zstd::Decoder::new(mmap.as_ref())?;
-            let geocoder = Tree2D::<i64, String>::read(file)?;
-            eprintln!("Open: {:?}", t.elapsed());
-            let t = Instant::now();
             let location = [(longitude * 1e9) as i64, (latitude * 1e9) as i64];
-            let neighbours = geocoder.find_nearest(&location, radius.0, limit, earth_distance);
-            eprintln!("Search: {:?}", t.elapsed());
-            for (_distance, [longitude, latitude], name) in neighbours.iter() {
+            // Flat files are queried without decoding.
+            let neighbours = if Tree2DRef::is_flat(mmap.as_ref()) {
+                let geocoder = Tree2DRef::new(mmap.as_ref())?;
+                eprintln!("Open: {:?}", t.elapsed());
+                let t = Instant::now();
+                let neighbours =
+                    geocoder.find_nearest(&location, radius.0, limit, &EarthDistance)?;
+                eprintln!("Search: {:?}", t.elapsed());
+                neighbours
+                    .into_iter()
+                    .map(|(_distance, location, name)| (location, name.to_string()))
+                    .collect::<Vec<_>>()
+            } else {
+                let file = zstd::Decoder::new(mmap.as_ref())?;
+                let geocoder = Tree2D::<i64, String>::read(file)?;
+                eprintln!("Open: {:?}", t.elapsed());
+                let t = Instant::now();
+                let neighbours =
+                    geocoder.find_nearest_by_metric(&location, radius.0, limit, &EarthDistance);
+                eprintln!("Search: {:?}", t.elapsed());
+                neighbours
+                    .into_iter()
+                    .map(|(_distance, location, name)| (*location, name.clone()))
+                    .collect()
+            };
+            for ([longitude, latitude], name) in neighbours.iter() {
                 println!(
                     "{:.9} {:.9} {name}",
                     *longitude as f64 * 1e-9,
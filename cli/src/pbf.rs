@@ -5,16 +5,24 @@ use osmpbf::Element;
 use osmpbf::ElementReader;
 use rayon::prelude::*;
 
+use crate::EarthDistance;
 use crate::Tree2D;
+use crate::TreeOptions;
 
 pub struct ImportedNodes {
     pub countries: Tree2D<i64, String>,
     pub settlements: Tree2D<i64, String>,
     pub other: Tree2D<i64, String>,
+    /// The number of nodes that were merged with the nodes with the same name.
+    pub num_merged: usize,
 }
 
 impl ImportedNodes {
-    pub fn import_pbf(file: &Path) -> std::io::Result<ImportedNodes> {
+    pub fn import_pbf(
+        file: &Path,
+        options: TreeOptions,
+        merge_radius: Option<u64>,
+    ) -> std::io::Result<ImportedNodes> {
         enum Kind {
             Country,
             Settlement,
@@ -100,10 +108,25 @@ impl ImportedNodes {
         settlements.par_sort_unstable();
         let mut countries = countries.into_inner().unwrap();
         countries.par_sort_unstable();
+        let mut num_merged = 0;
+        let mut build = |nodes| match merge_radius {
+            Some(radius) => {
+                let (tree, n) = Tree2D::par_from_nodes_collapsing_duplicates(
+                    nodes,
+                    options,
+                    radius,
+                    &EarthDistance,
+                );
+                num_merged += n;
+                tree
+            }
+            None => Tree2D::par_from_nodes_with_options(nodes, options),
+        };
         Ok(Self {
-            other: Tree2D::from_nodes(other),
-            settlements: Tree2D::from_nodes(settlements),
-            countries: Tree2D::from_nodes(countries),
+            other: build(other),
+            settlements: build(settlements),
+            countries: build(countries),
+            num_merged,
         })
     }
 }
@@ -4,6 +4,86 @@ mod earth;
 #[cfg(feature = "std")]
 pub use self::earth::*;
 
+/// Distance function that is aware of the splitting planes of [`TreeKD`](crate::TreeKD).
+///
+/// Besides the distance between two points the metric provides the lower bound of the distance
+/// between a point and any point on the other side of an axis-aligned plane.
+/// The tree uses the latter to skip the subtrees that can't contain nodes closer than the ones that
+/// were already found.
+pub trait Metric<C, const K: usize = 2> {
+    /// Distance type.
+    type Distance: Ord + Copy;
+
+    /// Returns the distance between two points.
+    fn distance(&self, a: &[C; K], b: &[C; K]) -> Self::Distance;
+
+    /// Returns the lower bound of the distance between `query` and any point that lies on the
+    /// plane `x[axis] = split_value` or on the other side of it.
+    fn axis_lower_bound(&self, query: &[C; K], axis: usize, split_value: C) -> Self::Distance;
+
+    /// Returns the lower bound of the distance between any point inside the box with corners
+    /// `min` and `max` and any point that lies on the plane `x[axis] = split_value` or on the other
+    /// side of it.
+    ///
+    /// The default implementation returns [`axis_lower_bound`](Self::axis_lower_bound) for
+    /// the point of the box that is closest to the plane along `axis`. This is correct if
+    /// the lower bound depends only on `query[axis]`; otherwise this method has to be overridden
+    /// (e.g. `EarthDistance` does this).
+    fn box_lower_bound(
+        &self,
+        min: &[C; K],
+        max: &[C; K],
+        axis: usize,
+        split_value: C,
+    ) -> Self::Distance
+    where
+        C: Ord + Copy,
+    {
+        let mut query = *min;
+        query[axis] = split_value.clamp(min[axis], max[axis]);
+        self.axis_lower_bound(&query, axis, split_value)
+    }
+}
+
+/// [`Metric`] that uses [`orthogonal_distance`] generalized to `K` dimensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct OrthogonalDistance;
+
+impl<const K: usize> Metric<i64, K> for OrthogonalDistance {
+    type Distance = u64;
+
+    fn distance(&self, a: &[i64; K], b: &[i64; K]) -> u64 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn axis_lower_bound(&self, query: &[i64; K], axis: usize, split_value: i64) -> u64 {
+        query[axis].abs_diff(split_value)
+    }
+}
+
+/// [`Metric`] that uses [`euclidean_distance_squared`] generalized to `K` dimensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct EuclideanDistanceSquared;
+
+impl<const K: usize> Metric<i64, K> for EuclideanDistanceSquared {
+    type Distance = u64;
+
+    fn distance(&self, a: &[i64; K], b: &[i64; K]) -> u64 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(a, b)| distance_squared_scalar(*a, *b))
+            .fold(0, u64::saturating_add)
+    }
+
+    fn axis_lower_bound(&self, query: &[i64; K], axis: usize, split_value: i64) -> u64 {
+        distance_squared_scalar(query[axis], split_value)
+    }
+}
+
 /// Returns maximum distance between two points computed along each axis individually,
 /// i.e. $ \mathrm{max}\left(\left|x_1 - x_0\right|, \left|y_1 - y_0\right|\right) $.
 pub fn orthogonal_distance(a: &[i64; 2], b: &[i64; 2]) -> u64 {
@@ -28,6 +108,23 @@ fn distance_squared_scalar(a: i64, b: i64) -> u64 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn box_lower_bound_works_in_many_dimensions() {
+        let min = [-10_i64; 100];
+        let max = [10_i64; 100];
+        for split_value in [-15_i64, -10, 0, 10, 15] {
+            let expected = split_value.abs_diff(split_value.clamp(-10, 10));
+            assert_eq!(
+                expected,
+                OrthogonalDistance.box_lower_bound(&min, &max, 99, split_value)
+            );
+            assert_eq!(
+                expected * expected,
+                EuclideanDistanceSquared.box_lower_bound(&min, &max, 99, split_value)
+            );
+        }
+    }
+
     #[test]
     fn earth_distance_works() {
         let d = earth_distance_f64(&[-77.0366, 38.8976], &[-75.1503, 39.9496]);
@@ -1,8 +1,11 @@
+use crate::Metric;
+
 const WGS_84_A: f64 = 6_378_137.0;
 const WGS_84_B: f64 = 6_356_752.314_2;
+pub(crate) const R_AVG: f64 = (WGS_84_A + WGS_84_B) * 0.5;
 
 #[inline]
-fn to_normal_vector(location: &[f64; 2]) -> [f64; 3] {
+pub(crate) fn to_normal_vector(location: &[f64; 2]) -> [f64; 3] {
     // https://en.wikipedia.org/wiki/N-vector
     let longitude = location[0];
     let latitude = location[1];
@@ -34,7 +37,7 @@ fn length(a: [f64; 3]) -> f64 {
     dot(a, a).sqrt()
 }
 
-fn to_f64(location: &[i64; 2]) -> [f64; 2] {
+pub(crate) fn to_f64(location: &[i64; 2]) -> [f64; 2] {
     let longitude = location[0] as f64 * 1e-9;
     let latitude = location[1] as f64 * 1e-9;
     [longitude, latitude]
@@ -56,8 +59,83 @@ pub fn earth_distance(a: &[i64; 2], b: &[i64; 2]) -> u64 {
 }
 
 pub(crate) fn earth_distance_f64(a: &[f64; 2], b: &[f64; 2]) -> f64 {
-    const R_AVG: f64 = (WGS_84_A + WGS_84_B) * 0.5;
     let n1 = to_normal_vector(a);
     let n2 = to_normal_vector(b);
     R_AVG * length(cross(n1, n2)).atan2(dot(n1, n2))
 }
+
+/// [`Metric`] that uses [`earth_distance`].
+///
+/// The lower bound for the latitude axis is the distance along the meridian to the parallel.
+/// The lower bound for the longitude axis is the cross-track distance to the meridian,
+/// or the distance to the nearest pole if the meridian is more than 90° away.
+/// Both shrink towards the poles as the meridians converge, i.e. the points on the opposite
+/// meridians near the pole are not skipped.
+///
+/// Longitude is cyclic: the half-plane on either side of a meridian is also bounded by
+/// the antimeridian, and the points on the other side of the antimeridian can be closer than
+/// the splitting meridian. Hence the lower bound for the longitude axis is the minimum of
+/// the distances to both meridians.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct EarthDistance;
+
+impl Metric<i64> for EarthDistance {
+    type Distance = u64;
+
+    fn distance(&self, a: &[i64; 2], b: &[i64; 2]) -> u64 {
+        earth_distance(a, b)
+    }
+
+    fn axis_lower_bound(&self, query: &[i64; 2], axis: usize, split_value: i64) -> u64 {
+        let location = to_f64(query);
+        let d = if axis == 0 {
+            let split_longitude = split_value as f64 * 1e-9;
+            meridian_distance(&location, split_longitude).min(meridian_distance(&location, 180.0))
+        } else {
+            R_AVG * (location[1] - split_value as f64 * 1e-9).abs().to_radians()
+        };
+        // Compensate for rounding errors.
+        (d as u64).saturating_sub(1)
+    }
+
+    fn box_lower_bound(
+        &self,
+        min: &[i64; 2],
+        max: &[i64; 2],
+        axis: usize,
+        split_value: i64,
+    ) -> u64 {
+        // The lower bound for the longitude axis also depends on the latitude: it decreases towards
+        // the poles, i.e. it reaches its minimum at one of the parallels that bound the box.
+        // Along the parallel the minimum is at the splitting meridian or at the meridians that
+        // bound the box (the latter are closer to the antimeridian).
+        let other = 1 - axis;
+        let clamped = split_value.clamp(min[axis], max[axis]);
+        [min[axis], max[axis], clamped]
+            .into_iter()
+            .flat_map(|x| {
+                [min[other], max[other]].map(|y| {
+                    let mut query = [0; 2];
+                    query[axis] = x;
+                    query[other] = y;
+                    self.axis_lower_bound(&query, axis, split_value)
+                })
+            })
+            .min()
+            .expect("At least one point")
+    }
+}
+
+/// Returns the distance between the point and the meridian that goes from pole to pole.
+fn meridian_distance(location: &[f64; 2], meridian: f64) -> f64 {
+    let [longitude, latitude] = *location;
+    let mut d_longitude = (longitude - meridian).abs() % 360.0;
+    if d_longitude > 180.0 {
+        d_longitude = 360.0 - d_longitude;
+    }
+    // The closest point is the nearest pole if the meridian is more than 90° away.
+    let sin_d_longitude = d_longitude.min(90.0).to_radians().sin();
+    let cos_latitude = latitude.to_radians().cos();
+    R_AVG * (cos_latitude * sin_d_longitude).clamp(0.0, 1.0).asin()
+}
@@ -1,180 +1,1176 @@
+use alloc::collections::BinaryHeap;
 use alloc::collections::VecDeque;
 use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::cmp::Ordering;
+use core::cmp::Reverse;
+use core::ops::Range;
 
+use crate::Metric;
+
+mod batch;
+mod duplicates;
+mod dynamic;
+#[cfg(feature = "std")]
+mod flat;
+mod implicit;
 #[cfg(feature = "std")]
 mod io;
+mod join;
+mod links;
+#[cfg(feature = "std")]
+mod nvector;
 #[cfg(feature = "std")]
 mod read;
+#[cfg(test)]
+mod testing;
+mod van_emde_boas;
 #[cfg(feature = "std")]
 mod write;
 
-const EMPTY: u32 = 0;
+pub use self::batch::*;
+pub use self::dynamic::*;
+#[cfg(feature = "std")]
+pub use self::flat::*;
+#[cfg(feature = "std")]
+pub use self::nvector::*;
+
+use self::implicit::ImplicitShape;
+use self::links::EMPTY;
+use self::links::Index;
+use self::links::Link;
+use self::links::Links;
+use self::van_emde_boas::van_emde_boas_order;
 
-#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
-struct Node<C, V> {
-    location: [C; 2],
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Node<C, V, const K: usize> {
+    location: [C; K],
     value: V,
-    lesser_index: u32,
-    greater_index: u32,
+}
+
+impl<C: Copy + Default, V: Default, const K: usize> Default for Node<C, V, K> {
+    fn default() -> Self {
+        Self {
+            location: [C::default(); K],
+            value: Default::default(),
+        }
+    }
+}
+
+/// The root of the subtree that is either a split node or a leaf bucket.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+struct Subtree {
+    /// The index of the splitting coordinate.
+    coord_index: usize,
+    /// The index of the split node or the index of the first node in the leaf bucket.
+    index: usize,
+    /// The number of nodes in the subtree.
+    ///
+    /// [`Layout::Implicit`] tracks the number of nodes only for the leaf buckets.
+    len: usize,
+}
+
+impl Subtree {
+    fn range(&self) -> Range<usize> {
+        self.index..self.index + self.len
+    }
+}
+
+/// Statistics collected during the search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    /// The number of nodes for which the distance was computed.
+    pub visited_nodes: usize,
+}
+
+/// An element of the priority queue in [`TreeKD::iter_nearest`].
+///
+/// Nodes go first to be returned before the subtrees with the same lower bound are expanded.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Entry {
+    /// Node index.
+    Node(usize),
+    Subtree(Subtree),
+}
+
+/// The closest nodes found so far.
+struct Neighbours<D> {
+    /// Max-heap of the distances and the node indices.
+    heap: BinaryHeap<(D, usize)>,
+    max_neighbours: usize,
+    max_distance: D,
+}
+
+impl<D: Ord + Copy> Neighbours<D> {
+    /// Create neighbours that reuse the memory of the `heap`.
+    fn new(mut heap: BinaryHeap<(D, usize)>, max_distance: D, max_neighbours: usize) -> Self {
+        heap.clear();
+        Self {
+            heap,
+            max_neighbours,
+            max_distance,
+        }
+    }
+
+    /// Returns `true` if the node at distance `d` can't be one of the neighbours.
+    fn skip(&self, d: D) -> bool {
+        d > self.max_distance || (self.heap.len() == self.max_neighbours && d == self.max_distance)
+    }
+
+    fn push(&mut self, d: D, i: usize) {
+        if self.skip(d) {
+            return;
+        }
+        if self.heap.len() == self.max_neighbours {
+            self.heap.pop();
+        }
+        self.heap.push((d, i));
+        if self.heap.len() == self.max_neighbours {
+            // We've already found enough neighbours; now we can limit our search to the
+            // ones that are closer than the farthest one found so far.
+            if let Some((d, _)) = self.heap.peek() {
+                self.max_distance = *d;
+            }
+        }
+    }
+}
+
+/// Memory buffers that are reused between the searches.
+struct Scratch<D> {
+    heap: BinaryHeap<(D, usize)>,
+    /// Subtrees to visit and the lower bounds of the distances to their nodes.
+    stack: Vec<(Subtree, Option<D>)>,
+}
+
+impl<D: Ord> Scratch<D> {
+    fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            stack: Vec::new(),
+        }
+    }
+}
+
+/// The nodes and the links of the tree.
+///
+/// The search algorithms that are generic over this trait are shared by [`TreeKD`] and its
+/// borrowed view in flat RGC format.
+trait Nodes<C: Ord + Copy, const K: usize> {
+    fn root(&self) -> Option<Subtree>;
+
+    /// Returns the lesser and the greater child of the split node or `None` if the subtree is
+    /// a leaf bucket.
+    fn children(&self, subtree: Subtree) -> Option<[Option<Subtree>; 2]>;
+
+    /// Returns the indices of the nodes in the leaf bucket.
+    fn bucket(&self, subtree: Subtree) -> Range<usize>;
+
+    fn split_value(&self, index: usize, coord_index: usize) -> C;
+
+    /// Returns the child that contains the `location` followed by the other child or `None` if
+    /// the subtree is a leaf bucket.
+    fn near_far(&self, subtree: Subtree, location: &[C; K]) -> Option<[Option<Subtree>; 2]> {
+        let [lesser, greater] = self.children(subtree)?;
+        let coord_index = subtree.coord_index;
+        if location[coord_index] < self.split_value(subtree.index, coord_index) {
+            Some([lesser, greater])
+        } else {
+            Some([greater, lesser])
+        }
+    }
+
+    /// Returns up to `max_neighbours` nodes within `max_distance` that are closest to the `location`.
+    ///
+    /// The distance to the node with the specified index is computed by `distance` that returns
+    /// `None` for the nodes that are not counted towards `max_neighbours`. The lower bound of
+    /// the distance to any node on the other side of the splitting plane is computed by
+    /// `axis_lower_bound`.
+    /// The distances and the indices of the nodes are passed to `output` in the order of
+    /// non-decreasing distance.
+    #[allow(clippy::too_many_arguments)]
+    fn find_nearest_indices<D: Ord + Copy>(
+        &self,
+        location: &[C; K],
+        max_distance: D,
+        max_neighbours: usize,
+        mut distance: impl FnMut(usize) -> Option<D>,
+        mut axis_lower_bound: impl FnMut(usize, C) -> D,
+        stats: &mut SearchStats,
+        scratch: &mut Scratch<D>,
+        mut output: impl FnMut(D, usize),
+    ) {
+        let Some(root) = self.root() else {
+            return;
+        };
+        if max_neighbours == 0 {
+            return;
+        }
+        let mut neighbours = Neighbours::new(
+            core::mem::take(&mut scratch.heap),
+            max_distance,
+            max_neighbours,
+        );
+        let stack = &mut scratch.stack;
+        stack.clear();
+        // The second element is the lower bound of the distance to any node in the subtree.
+        stack.push((root, None));
+        while let Some((subtree, lower_bound)) = stack.pop() {
+            if lower_bound.is_some_and(|d| neighbours.skip(d)) {
+                continue;
+            }
+            let mut visit = |i: usize| {
+                stats.visited_nodes += 1;
+                if let Some(d) = distance(i) {
+                    neighbours.push(d, i);
+                }
+            };
+            let Some([near, far]) = self.near_far(subtree, location) else {
+                self.bucket(subtree).for_each(visit);
+                continue;
+            };
+            visit(subtree.index);
+            // The far child is pushed first to be visited last.
+            if let Some(far) = far {
+                let plane_distance = axis_lower_bound(
+                    subtree.coord_index,
+                    self.split_value(subtree.index, subtree.coord_index),
+                );
+                if plane_distance <= neighbours.max_distance {
+                    let lower_bound = lower_bound.map_or(plane_distance, |d| d.max(plane_distance));
+                    stack.push((far, Some(lower_bound)));
+                }
+            }
+            if let Some(near) = near {
+                stack.push((near, lower_bound));
+            }
+        }
+        let mut sorted = neighbours.heap.into_sorted_vec();
+        for (d, i) in sorted.drain(..) {
+            output(d, i);
+        }
+        scratch.heap = BinaryHeap::from(sorted);
+    }
+}
+
+/// The order in which the nodes of [`TreeKD`] are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Layout {
+    /// Median-split tree in breadth-first order.
+    ///
+    /// Each split node stores the indices of its children.
+    #[default]
+    Explicit,
+    /// Balanced tree in Eytzinger order.
+    ///
+    /// The split nodes form a perfect binary tree, i.e. the indices of the children are computed
+    /// rather than stored. The leaf buckets are as full as in [`Layout::Explicit`].
+    Implicit,
+    /// Median-split tree in van Emde Boas order.
+    ///
+    /// The tree is recursively split at half of its height into the top subtree and the bottom
+    /// subtrees, and each of them is stored contiguously, i.e. the nodes that are visited one
+    /// after another are close in memory regardless of the size of the cache line or the page.
+    /// Each split node stores the indices of its children.
+    VanEmdeBoas,
+}
+
+/// Options that control the layout of [`TreeKD`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TreeOptions {
+    leaf_size: usize,
+    layout: Layout,
+}
+
+impl TreeOptions {
+    /// Create default options.
+    pub const fn new() -> Self {
+        Self {
+            leaf_size: 1,
+            layout: Layout::Explicit,
+        }
+    }
+
+    /// Sets the maximum number of nodes in a leaf bucket.
+    ///
+    /// The subtrees that have no more than `leaf_size` nodes are stored as leaf buckets.
+    /// The nodes in a bucket are scanned linearly and don't store the indices of their children,
+    /// i.e. larger buckets use less memory but require computing more distances.
+    /// The default is 1, i.e. every leaf is a single node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `leaf_size` is zero.
+    pub const fn leaf_size(mut self, leaf_size: usize) -> Self {
+        assert!(leaf_size != 0);
+        self.leaf_size = leaf_size;
+        self
+    }
+
+    /// Sets the order in which the nodes are stored.
+    ///
+    /// The default is [`Layout::Explicit`].
+    pub const fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+/// The shape of the tree that depends only on the number of nodes and the options.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Shape {
+    Explicit { leaf_size: usize },
+    Implicit(ImplicitShape),
+}
+
+impl Shape {
+    fn new(len: usize, options: TreeOptions) -> Self {
+        match options.layout {
+            Layout::Explicit | Layout::VanEmdeBoas => Self::Explicit {
+                leaf_size: options.leaf_size,
+            },
+            Layout::Implicit => Self::Implicit(ImplicitShape::new(len, options.leaf_size)),
+        }
+    }
+
+    /// Returns the number of nodes in the lesser subtree and the slots of the children of
+    /// the subtree that occupies the `slot`, or `None` if the subtree is a leaf bucket.
+    ///
+    /// [`Layout::Explicit`] doesn't use slots.
+    fn split(&self, slot: usize, len: usize) -> Option<(usize, [usize; 2])> {
+        match self {
+            Self::Explicit { leaf_size } => (len > *leaf_size).then_some((len / 2, [0, 0])),
+            Self::Implicit(shape) => (slot < shape.num_splits).then(|| {
+                let children = [2 * slot + 1, 2 * slot + 2];
+                (shape.subtree_len(children[0]), children)
+            }),
+        }
+    }
+
+    /// Returns the lesser and the greater child of the split node or `None` if the subtree is
+    /// a leaf bucket.
+    ///
+    /// The one-based indices of the children of the split node in [`Layout::Explicit`] are
+    /// returned by `links`.
+    fn children<const K: usize>(
+        &self,
+        subtree: Subtree,
+        links: impl FnOnce(usize) -> [usize; 2],
+    ) -> Option<[Option<Subtree>; 2]> {
+        let leaf_size = match self {
+            Shape::Explicit { leaf_size } => *leaf_size,
+            Shape::Implicit(shape) => return Self::implicit_children::<K>(subtree, shape),
+        };
+        if subtree.len <= leaf_size {
+            return None;
+        }
+        let [lesser_index, greater_index] = links(subtree.index);
+        let coord_index = (subtree.coord_index + 1) % K;
+        let lesser_len = subtree.len / 2;
+        let greater_len = subtree.len - lesser_len - 1;
+        let child = |index: usize, len: usize| match index {
+            EMPTY => None,
+            i => Some(Subtree {
+                coord_index,
+                index: i - 1,
+                len,
+            }),
+        };
+        Some([
+            child(lesser_index, lesser_len),
+            child(greater_index, greater_len),
+        ])
+    }
+
+    fn implicit_children<const K: usize>(
+        subtree: Subtree,
+        shape: &ImplicitShape,
+    ) -> Option<[Option<Subtree>; 2]> {
+        // Split nodes occupy the slots with the same indices.
+        if subtree.index >= shape.num_splits {
+            return None;
+        }
+        let coord_index = (subtree.coord_index + 1) % K;
+        let child = |slot: usize| {
+            if slot < shape.num_splits {
+                return Some(Subtree {
+                    coord_index,
+                    index: slot,
+                    len: 0,
+                });
+            }
+            let bucket = shape.bucket(slot);
+            (!bucket.is_empty()).then_some(Subtree {
+                coord_index,
+                index: bucket.start,
+                len: bucket.len(),
+            })
+        };
+        Some([child(2 * subtree.index + 1), child(2 * subtree.index + 2)])
+    }
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Two-dimensional tree that maps a location given by `[i64; 2]` to a string.
 pub type NamesTree = Tree2D<i64, String>;
 
 /// Two-dimensional tree that maps a location given by `[C; 2]` to a value `V`.
+pub type Tree2D<C, V> = TreeKD<C, V, 2>;
+
+/// `K`-dimensional tree that maps a location given by `[C; K]` to a value `V`.
+///
+/// The split nodes are stored first (see [`Layout`]) followed by the leaf buckets
+/// (see [`TreeOptions::leaf_size`]).
 ///
 /// # References
 ///
 /// - <https://en.wikipedia.org/wiki/K-d_tree>
 #[derive(Debug, PartialEq, Eq)]
-pub struct Tree2D<C, V> {
-    nodes: Vec<Node<C, V>>,
+pub struct TreeKD<C, V, const K: usize> {
+    nodes: Vec<Node<C, V, K>>,
+    /// The children of the split nodes in [`Layout::Explicit`] and [`Layout::VanEmdeBoas`].
+    links: Links,
+    shape: Shape,
+    options: TreeOptions,
 }
 
-impl<C: Ord + Copy + Default, V: Default> Tree2D<C, V> {
+impl<C: Ord + Copy + Default, V: Default, const K: usize> TreeKD<C, V, K> {
     /// Create a new tree from the given nodes.
     ///
     /// The nodes are recursively subdividded into two groups: one that is behind and one that is
     /// in front of the plane that goes through the median node.
-    /// The plane alternates between _x = 0_ and _y = 0_ for each layer of the tree.
+    /// The plane alternates between _x = 0_, _y = 0_ etc. for each layer of the tree.
+    ///
+    /// The nodes that have the same coordinate are ordered by the other coordinates and then by
+    /// the value, i.e. the layout of the tree doesn't depend on the order of the nodes.
     ///
     /// The values are moved from the vector without copying.
-    pub fn from_nodes(mut nodes: Vec<([C; 2], V)>) -> Self {
-        assert!(nodes.len() < u32::MAX as usize);
+    pub fn from_nodes(nodes: Vec<([C; K], V)>) -> Self
+    where
+        V: Ord,
+    {
+        Self::from_nodes_with_options(nodes, TreeOptions::default())
+    }
+
+    /// The same as [`from_nodes`](Self::from_nodes) but with the specified `options`.
+    pub fn from_nodes_with_options(mut nodes: Vec<([C; K], V)>, options: TreeOptions) -> Self
+    where
+        V: Ord,
+    {
+        const { assert!(K != 0) };
+        let shape = Shape::new(nodes.len(), options);
+        partition(&mut nodes, 0, &shape, 0);
+        Self::from_partitioned_nodes(nodes, shape, options)
+    }
+
+    /// Create a new tree from the given nodes in parallel.
+    ///
+    /// The subtrees are partitioned in parallel using [`rayon::join`].
+    /// The resulting tree is the same as the one created by [`from_nodes`](Self::from_nodes).
+    #[cfg(feature = "rayon")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rayon")))]
+    pub fn par_from_nodes(nodes: Vec<([C; K], V)>) -> Self
+    where
+        C: Send,
+        V: Ord + Send,
+    {
+        Self::par_from_nodes_with_options(nodes, TreeOptions::default())
+    }
+
+    /// The same as [`par_from_nodes`](Self::par_from_nodes) but with the specified `options`.
+    #[cfg(feature = "rayon")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rayon")))]
+    pub fn par_from_nodes_with_options(mut nodes: Vec<([C; K], V)>, options: TreeOptions) -> Self
+    where
+        C: Send,
+        V: Ord + Send,
+    {
+        const { assert!(K != 0) };
+        let shape = Shape::new(nodes.len(), options);
+        par_partition(&mut nodes, 0, &shape, 0);
+        Self::from_partitioned_nodes(nodes, shape, options)
+    }
+
+    /// Lays out the nodes that were partitioned by [`partition`].
+    fn from_partitioned_nodes(nodes: Vec<([C; K], V)>, shape: Shape, options: TreeOptions) -> Self {
+        let (nodes, links) = match shape {
+            Shape::Explicit { leaf_size } if Links::is_wide(nodes.len()) => {
+                let (nodes, links) = Self::explicit_layout(nodes, leaf_size, options.layout);
+                (nodes, Links::Wide(links))
+            }
+            Shape::Explicit { leaf_size } => {
+                let (nodes, links) = Self::explicit_layout(nodes, leaf_size, options.layout);
+                (nodes, Links::Narrow(links))
+            }
+            Shape::Implicit(shape) => (
+                Self::implicit_layout(nodes, &shape),
+                Links::Narrow(Vec::new()),
+            ),
+        };
+        Self {
+            nodes,
+            links,
+            shape,
+            options,
+        }
+    }
+
+    /// Lays out the nodes in breadth-first order, and then reorders them according to the `layout`.
+    #[allow(clippy::type_complexity)]
+    fn explicit_layout<I: Index>(
+        mut nodes: Vec<([C; K], V)>,
+        leaf_size: usize,
+        layout: Layout,
+    ) -> (Vec<Node<C, V, K>>, Vec<Link<I>>) {
+        let num_splits = num_splits(nodes.len(), leaf_size);
         let mut output_nodes = Vec::with_capacity(nodes.len());
         for _ in 0..nodes.len() {
-            output_nodes.push(Node {
-                location: Default::default(),
-                value: Default::default(),
-                lesser_index: EMPTY,
-                greater_index: EMPTY,
-            });
+            output_nodes.push(Node::default());
         }
-        let mut output_node_index: u32 = 1;
-        let mut next_output_node_index = || {
-            let i = output_node_index;
-            output_node_index += 1;
-            i
+        let mut links = vec![Link::default(); num_splits];
+        // Split nodes go first, leaf buckets go after them.
+        let mut next_split_index = 0;
+        let mut next_bucket_index = num_splits;
+        let mut next_output_node_index = |len: usize| -> usize {
+            let i = if len == 0 {
+                return EMPTY;
+            } else if len <= leaf_size {
+                let i = next_bucket_index;
+                next_bucket_index += len;
+                i
+            } else {
+                let i = next_split_index;
+                next_split_index += 1;
+                i
+            };
+            i + 1
         };
         let mut queue = VecDeque::new();
-        queue.push_back((0, next_output_node_index(), nodes.as_mut_slice()));
-        while let Some((coord_index, i, nodes)) = queue.pop_front() {
-            let next_coord_index = (coord_index + 1) % 2;
-            let nodes_len = nodes.len();
-            if nodes_len == 0 {
-                break;
-            }
-            if nodes_len == 1 {
-                output_nodes[(i - 1) as usize] = Node {
-                    location: nodes[0].0,
-                    value: core::mem::take(&mut nodes[0].1),
-                    lesser_index: EMPTY,
-                    greater_index: EMPTY,
-                };
+        let root_index = next_output_node_index(nodes.len());
+        if root_index != EMPTY {
+            queue.push_back((root_index, nodes.as_mut_slice()));
+        }
+        while let Some((i, nodes)) = queue.pop_front() {
+            let i = i - 1;
+            if nodes.len() <= leaf_size {
+                for (output_node, (location, value)) in
+                    output_nodes[i..i + nodes.len()].iter_mut().zip(nodes)
+                {
+                    *output_node = Node {
+                        location: *location,
+                        value: core::mem::take(value),
+                    };
+                }
                 continue;
             }
-            let (lesser_nodes, median, greater_nodes) = nodes
-                .select_nth_unstable_by(nodes_len / 2, |a, b| {
-                    a.0[coord_index].cmp(&b.0[coord_index])
-                });
-            let lesser_index = if !lesser_nodes.is_empty() {
-                let i = next_output_node_index();
-                queue.push_back((next_coord_index, i, lesser_nodes));
-                i
-            } else {
-                EMPTY
+            let (lesser_nodes, rest) = nodes.split_at_mut(nodes.len() / 2);
+            let (median, greater_nodes) = rest.split_first_mut().expect("non-empty");
+            let lesser_index = next_output_node_index(lesser_nodes.len());
+            if lesser_index != EMPTY {
+                queue.push_back((lesser_index, lesser_nodes));
+            }
+            let greater_index = next_output_node_index(greater_nodes.len());
+            if greater_index != EMPTY {
+                queue.push_back((greater_index, greater_nodes));
+            }
+            output_nodes[i] = Node {
+                location: median.0,
+                value: core::mem::take(&mut median.1),
             };
-            let greater_index = if !greater_nodes.is_empty() {
-                let i = next_output_node_index();
-                queue.push_back((next_coord_index, i, greater_nodes));
-                i
-            } else {
-                EMPTY
+            links[i] = Link {
+                lesser_index: I::from_usize(lesser_index),
+                greater_index: I::from_usize(greater_index),
             };
-            output_nodes[(i - 1) as usize] = Node {
+        }
+        match layout {
+            Layout::VanEmdeBoas => Self::van_emde_boas_layout(output_nodes, links),
+            _ => (output_nodes, links),
+        }
+    }
+
+    /// Reorders the split nodes that were laid out in breadth-first order in van Emde Boas order.
+    #[allow(clippy::type_complexity)]
+    fn van_emde_boas_layout<I: Index>(
+        mut nodes: Vec<Node<C, V, K>>,
+        links: Vec<Link<I>>,
+    ) -> (Vec<Node<C, V, K>>, Vec<Link<I>>) {
+        let num_splits = links.len();
+        let order = van_emde_boas_order(&links);
+        let mut new_indices = vec![0; num_splits];
+        for (new_index, old_index) in order.iter().enumerate() {
+            new_indices[*old_index] = new_index;
+        }
+        // The leaf buckets stay in place.
+        let new_index = |i: I| match i.to_usize() {
+            EMPTY => i,
+            j if j > num_splits => i,
+            j => I::from_usize(new_indices[j - 1] + 1),
+        };
+        let new_links = order
+            .iter()
+            .map(|i| Link {
+                lesser_index: new_index(links[*i].lesser_index),
+                greater_index: new_index(links[*i].greater_index),
+            })
+            .collect();
+        let mut new_nodes = Vec::with_capacity(nodes.len());
+        new_nodes.extend(order.iter().map(|i| core::mem::take(&mut nodes[*i])));
+        new_nodes.extend(nodes.drain(num_splits..));
+        (new_nodes, new_links)
+    }
+
+    /// Lays out the nodes in Eytzinger order.
+    fn implicit_layout(mut nodes: Vec<([C; K], V)>, shape: &ImplicitShape) -> Vec<Node<C, V, K>> {
+        let mut output_nodes = Vec::with_capacity(nodes.len());
+        for _ in 0..nodes.len() {
+            output_nodes.push(Node::default());
+        }
+        let mut queue = VecDeque::new();
+        queue.push_back((0, nodes.as_mut_slice()));
+        while let Some((slot, nodes)) = queue.pop_front() {
+            if slot >= shape.num_splits {
+                let bucket = shape.bucket(slot);
+                debug_assert_eq!(bucket.len(), nodes.len());
+                for (output_node, (location, value)) in output_nodes[bucket].iter_mut().zip(nodes) {
+                    *output_node = Node {
+                        location: *location,
+                        value: core::mem::take(value),
+                    };
+                }
+                continue;
+            }
+            let (lesser_nodes, rest) = nodes.split_at_mut(shape.subtree_len(2 * slot + 1));
+            let (median, greater_nodes) = rest.split_first_mut().expect("non-empty");
+            queue.push_back((2 * slot + 1, lesser_nodes));
+            queue.push_back((2 * slot + 2, greater_nodes));
+            output_nodes[slot] = Node {
                 location: median.0,
                 value: core::mem::take(&mut median.1),
-                lesser_index,
-                greater_index,
             };
         }
-        Self {
-            nodes: output_nodes,
+        output_nodes
+    }
+
+    /// Merges two trees into one.
+    ///
+    /// The nodes are moved out of both trees without copying the values,
+    /// and then the tree is rebuilt using [`from_nodes_with_options`](Self::from_nodes_with_options)
+    /// with the options of the first tree.
+    pub fn merge(self, other: Self) -> Self
+    where
+        V: Ord,
+    {
+        Self::merge_all([self, other])
+    }
+
+    /// Merges all trees into one.
+    ///
+    /// The nodes are moved out of the trees without copying the values,
+    /// and then the tree is rebuilt using [`from_nodes_with_options`](Self::from_nodes_with_options)
+    /// with the options of the first tree.
+    pub fn merge_all(trees: impl IntoIterator<Item = Self>) -> Self
+    where
+        V: Ord,
+    {
+        let trees: Vec<_> = trees.into_iter().collect();
+        let options = trees.first().map(|tree| tree.options).unwrap_or_default();
+        let mut nodes = Vec::with_capacity(trees.iter().map(Self::len).sum());
+        for tree in trees {
+            nodes.extend(tree.into_nodes());
         }
+        Self::from_nodes_with_options(nodes, options)
+    }
+
+    /// Returns the options that were used to build the tree.
+    pub fn options(&self) -> TreeOptions {
+        self.options
     }
 
     /// Returns up to `max_neighbours` nodes within `max_distance` that are closest to the `location`.
     ///
     /// The distance between nodes is computed using `calc_distance`.
+    /// The distance to a splitting plane is computed as the distance to the projection of the
+    /// `location` onto the plane, hence `calc_distance` has to be monotone in every coordinate.
+    /// This isn't the case for `earth_distance` (longitude wraps around):
+    /// use [`find_nearest_by_metric`](Self::find_nearest_by_metric) with `EarthDistance` instead.
     pub fn find_nearest<D>(
         &self,
-        location: &[C; 2],
-        mut max_distance: D,
+        location: &[C; K],
+        max_distance: D,
         max_neighbours: usize,
-        mut calc_distance: impl FnMut(&[C; 2], &[C; 2]) -> D,
-    ) -> Vec<(D, &[C; 2], &V)>
+        calc_distance: impl FnMut(&[C; K], &[C; K]) -> D,
+    ) -> Vec<(D, &[C; K], &V)>
     where
-        D: Ord + Copy + core::fmt::Display,
+        D: Ord + Copy,
     {
+        let calc_distance = RefCell::new(calc_distance);
         let mut neighbours = Vec::new();
-        if max_neighbours == 0 {
-            return neighbours;
+        self.find_nearest_impl(
+            location,
+            max_distance,
+            max_neighbours,
+            |other, _| Some((calc_distance.borrow_mut())(other, location)),
+            |axis, split_value| {
+                let mut projection = *location;
+                projection[axis] = split_value;
+                (calc_distance.borrow_mut())(&projection, location)
+            },
+            &mut SearchStats::default(),
+            &mut Scratch::new(),
+            |d, i| neighbours.push(self.neighbour(d, i)),
+        );
+        neighbours
+    }
+
+    /// Returns up to `max_neighbours` nodes within `max_distance` that are closest to the `location`.
+    ///
+    /// The distance between nodes is computed using `metric`.
+    /// The tree is traversed depth-first, and the child that contains the `location` is visited
+    /// first. This quickly tightens the search radius: a subtree is skipped when the lower bound of
+    /// the distance to its splitting plane exceeds the distance to the farthest of the
+    /// `max_neighbours` nodes found so far.
+    pub fn find_nearest_by_metric<M: Metric<C, K>>(
+        &self,
+        location: &[C; K],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+    ) -> Vec<(M::Distance, &[C; K], &V)> {
+        if max_neighbours == 1 {
+            return self
+                .find_nearest_one(location, max_distance, metric)
+                .into_iter()
+                .collect();
         }
-        // TODO optimize for max_neighbours == 1
-        let Some(root) = self.nodes.first() else {
-            return neighbours;
+        let mut stats = SearchStats::default();
+        self.find_nearest_with_stats(location, max_distance, max_neighbours, metric, &mut stats)
+    }
+
+    /// The same as [`find_nearest_by_metric`](Self::find_nearest_by_metric) but also collects
+    /// search statistics.
+    pub fn find_nearest_with_stats<M: Metric<C, K>>(
+        &self,
+        location: &[C; K],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+        stats: &mut SearchStats,
+    ) -> Vec<(M::Distance, &[C; K], &V)> {
+        let mut neighbours = Vec::new();
+        self.find_nearest_impl(
+            location,
+            max_distance,
+            max_neighbours,
+            |other, _| Some(metric.distance(other, location)),
+            |axis, split_value| metric.axis_lower_bound(location, axis, split_value),
+            stats,
+            &mut Scratch::new(),
+            |d, i| neighbours.push(self.neighbour(d, i)),
+        );
+        neighbours
+    }
+
+    /// Returns up to `max_neighbours` nodes within `max_distance` that are closest to the `location`
+    /// and satisfy the `predicate`.
+    ///
+    /// Only the nodes for which `predicate` returns `true` are counted towards `max_neighbours`,
+    /// i.e. the search continues until enough such nodes are found.
+    pub fn find_nearest_where<M: Metric<C, K>>(
+        &self,
+        location: &[C; K],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+        mut predicate: impl FnMut(&[C; K], &V) -> bool,
+    ) -> Vec<(M::Distance, &[C; K], &V)> {
+        let mut stats = SearchStats::default();
+        let mut neighbours = Vec::new();
+        self.find_nearest_impl(
+            location,
+            max_distance,
+            max_neighbours,
+            |other, value| {
+                let d = metric.distance(other, location);
+                predicate(other, value).then_some(d)
+            },
+            |axis, split_value| metric.axis_lower_bound(location, axis, split_value),
+            &mut stats,
+            &mut Scratch::new(),
+            |d, i| neighbours.push(self.neighbour(d, i)),
+        );
+        neighbours
+    }
+
+    /// Returns up to `max_neighbours` nodes within `max_distance` that are approximately closest to
+    /// the `location`.
+    ///
+    /// The search skips the subtrees that can't contain nodes closer than
+    /// `d / (1 + epsilon)`, where `d` is the distance to the farthest of the `max_neighbours` nodes
+    /// found so far. This guarantees that the distance to the _i_-th returned node doesn't exceed
+    /// `1 + epsilon` times the distance to the true _i_-th nearest node, and that the nodes closer
+    /// than `max_distance / (1 + epsilon)` are never missed.
+    /// The bound applies to the values of `metric`, e.g. for
+    /// [`EuclideanDistanceSquared`](crate::EuclideanDistanceSquared) the Euclidean distance is
+    /// within `sqrt(1 + epsilon)` of the true one.
+    /// Zero `epsilon` gives the same result as [`find_nearest_by_metric`](Self::find_nearest_by_metric).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `epsilon` is negative or NaN.
+    pub fn find_nearest_approx<M: Metric<C, K, Distance = u64>>(
+        &self,
+        location: &[C; K],
+        max_distance: u64,
+        max_neighbours: usize,
+        epsilon: f64,
+        metric: &M,
+    ) -> Vec<(u64, &[C; K], &V)> {
+        let mut stats = SearchStats::default();
+        self.find_nearest_approx_with_stats(
+            location,
+            max_distance,
+            max_neighbours,
+            epsilon,
+            metric,
+            &mut stats,
+        )
+    }
+
+    fn find_nearest_approx_with_stats<M: Metric<C, K, Distance = u64>>(
+        &self,
+        location: &[C; K],
+        max_distance: u64,
+        max_neighbours: usize,
+        epsilon: f64,
+        metric: &M,
+        stats: &mut SearchStats,
+    ) -> Vec<(u64, &[C; K], &V)> {
+        assert!(epsilon >= 0.0);
+        let mut neighbours = Vec::new();
+        self.find_nearest_impl(
+            location,
+            max_distance,
+            max_neighbours,
+            |other, _| Some(metric.distance(other, location)),
+            |axis, split_value| {
+                // Inflating the lower bound is the same as deflating the search radius.
+                let d = metric.axis_lower_bound(location, axis, split_value);
+                d.saturating_add((d as f64 * epsilon) as u64)
+            },
+            stats,
+            &mut Scratch::new(),
+            |d, i| neighbours.push(self.neighbour(d, i)),
+        );
+        neighbours
+    }
+
+    /// The same as [`Nodes::find_nearest_indices`] but `distance` takes the location and the value
+    /// of the node.
+    #[allow(clippy::too_many_arguments)]
+    fn find_nearest_impl<D: Ord + Copy>(
+        &self,
+        location: &[C; K],
+        max_distance: D,
+        max_neighbours: usize,
+        mut distance: impl FnMut(&[C; K], &V) -> Option<D>,
+        axis_lower_bound: impl FnMut(usize, C) -> D,
+        stats: &mut SearchStats,
+        scratch: &mut Scratch<D>,
+        output: impl FnMut(D, usize),
+    ) {
+        self.find_nearest_indices(
+            location,
+            max_distance,
+            max_neighbours,
+            |i| {
+                let node = &self.nodes[i];
+                distance(&node.location, &node.value)
+            },
+            axis_lower_bound,
+            stats,
+            scratch,
+            output,
+        );
+    }
+
+    fn neighbour<D>(&self, d: D, i: usize) -> (D, &[C; K], &V) {
+        let node = &self.nodes[i];
+        (d, &node.location, &node.value)
+    }
+
+    /// Returns the node within `max_distance` that is closest to the `location`.
+    ///
+    /// This is the same as [`find_nearest_by_metric`](Self::find_nearest_by_metric) with
+    /// `max_neighbours == 1` but without memory allocations:
+    /// the tree is traversed depth-first, and the child that contains the `location` is visited first.
+    pub fn find_nearest_one<M: Metric<C, K>>(
+        &self,
+        location: &[C; K],
+        max_distance: M::Distance,
+        metric: &M,
+    ) -> Option<(M::Distance, &[C; K], &V)> {
+        self.find_nearest_one_impl(
+            location,
+            max_distance,
+            &|other, _| metric.distance(other, location),
+            &|axis, split_value| metric.axis_lower_bound(location, axis, split_value),
+        )
+        .map(|(d, i)| self.neighbour(d, i))
+    }
+
+    /// Returns the distance and the index of the node within `max_distance` that is closest to
+    /// the `location`.
+    ///
+    /// The distance to the node is computed by `distance`, and the lower bound of the distance to
+    /// any node on the other side of the splitting plane is computed by `axis_lower_bound`.
+    fn find_nearest_one_impl<D: Ord + Copy>(
+        &self,
+        location: &[C; K],
+        max_distance: D,
+        distance: &impl Fn(&[C; K], &V) -> D,
+        axis_lower_bound: &impl Fn(usize, C) -> D,
+    ) -> Option<(D, usize)> {
+        let root = self.root()?;
+        let mut nearest = None;
+        self.find_nearest_one_recursive(
+            root,
+            location,
+            max_distance,
+            distance,
+            axis_lower_bound,
+            &mut nearest,
+        );
+        nearest
+    }
+
+    fn find_nearest_one_recursive<D: Ord + Copy>(
+        &self,
+        subtree: Subtree,
+        location: &[C; K],
+        max_distance: D,
+        distance: &impl Fn(&[C; K], &V) -> D,
+        axis_lower_bound: &impl Fn(usize, C) -> D,
+        nearest: &mut Option<(D, usize)>,
+    ) {
+        let mut visit = |i: usize| {
+            let node = &self.nodes[i];
+            let d = distance(&node.location, &node.value);
+            match nearest {
+                Some((nearest_distance, _)) if d >= *nearest_distance => {}
+                _ if d > max_distance => {}
+                _ => *nearest = Some((d, i)),
+            }
         };
-        let mut queue = VecDeque::new();
-        queue.push_back((0, root));
-        while let Some((coord_index, node)) = queue.pop_front() {
-            let d = calc_distance(&node.location, location);
-            let mut lesser = false;
-            let mut greater = false;
-            if d.le(&max_distance) {
-                match neighbours.binary_search_by(|(distance, ..)| distance.cmp(&d)) {
-                    Err(i) if i == max_neighbours => {}
-                    Ok(i) | Err(i) => {
-                        if neighbours.len() == max_neighbours {
-                            neighbours.pop();
-                        }
-                        neighbours.insert(i, (d, &node.location, &node.value));
+        let Some([near, far]) = self.near_far(subtree, location) else {
+            subtree.range().for_each(visit);
+            return;
+        };
+        visit(subtree.index);
+        if let Some(near) = near {
+            self.find_nearest_one_recursive(
+                near,
+                location,
+                max_distance,
+                distance,
+                axis_lower_bound,
+                nearest,
+            );
+        }
+        if let Some(far) = far {
+            let plane_distance = axis_lower_bound(
+                subtree.coord_index,
+                self.split_value(subtree.index, subtree.coord_index),
+            );
+            let skip = match nearest {
+                Some((nearest_distance, _)) => plane_distance >= *nearest_distance,
+                None => plane_distance > max_distance,
+            };
+            if !skip {
+                self.find_nearest_one_recursive(
+                    far,
+                    location,
+                    max_distance,
+                    distance,
+                    axis_lower_bound,
+                    nearest,
+                );
+            }
+        }
+    }
+
+    /// Returns an iterator over all nodes in the order of non-decreasing distance from the `location`.
+    ///
+    /// The distance is computed using `metric`.
+    /// The nodes are found incrementally using a priority queue of subtrees and nodes, i.e. the
+    /// iterator can be stopped as soon as the desired node is found without knowing the number of
+    /// neighbours in advance.
+    pub fn iter_nearest<M: Metric<C, K>>(
+        &self,
+        location: &[C; K],
+        metric: &M,
+    ) -> impl Iterator<Item = (M::Distance, &[C; K], &V)> {
+        // Min-heap of subtrees and nodes ordered by the (lower bound of the) distance.
+        // The subtrees of the root have no lower bound, and `None` is less than any distance.
+        let mut queue = BinaryHeap::new();
+        if let Some(root) = self.root() {
+            queue.push(Reverse((None, Entry::Subtree(root))));
+        }
+        core::iter::from_fn(move || {
+            while let Some(Reverse((lower_bound, entry))) = queue.pop() {
+                let subtree = match entry {
+                    Entry::Node(i) => {
+                        // Nodes always have the distance.
+                        let node = &self.nodes[i];
+                        return lower_bound.map(|d| (d, &node.location, &node.value));
                     }
+                    Entry::Subtree(subtree) => subtree,
+                };
+                let mut push_node = |i: usize| {
+                    let d = metric.distance(&self.nodes[i].location, location);
+                    queue.push(Reverse((Some(d), Entry::Node(i))));
+                };
+                let Some([near, far]) = self.near_far(subtree, location) else {
+                    subtree.range().for_each(push_node);
+                    continue;
+                };
+                push_node(subtree.index);
+                if let Some(near) = near {
+                    queue.push(Reverse((lower_bound, Entry::Subtree(near))));
                 }
-                if neighbours.len() == max_neighbours {
-                    // We've already found enough neighbours; now we can limit our search to the
-                    // ones that are closer than the closest one found so far.
-                    max_distance = neighbours[0].0;
+                if let Some(far) = far {
+                    let plane_distance = metric.axis_lower_bound(
+                        location,
+                        subtree.coord_index,
+                        self.split_value(subtree.index, subtree.coord_index),
+                    );
+                    let lower_bound = lower_bound.map_or(plane_distance, |d| d.max(plane_distance));
+                    queue.push(Reverse((Some(lower_bound), Entry::Subtree(far))));
                 }
-                lesser = true;
-                greater = true;
-            } else if location[coord_index] < node.location[coord_index] {
-                lesser = true;
-            } else {
-                greater = true;
             }
-            let next_coord_index = (coord_index + 1) % 2;
-            if lesser && node.lesser_index != EMPTY {
-                queue.push_back((
-                    next_coord_index,
-                    &self.nodes[(node.lesser_index - 1) as usize],
-                ));
+            None
+        })
+    }
+
+    /// Returns an iterator over nodes within `radius` from the `location`.
+    ///
+    /// The nodes are returned in no particular order together with the distance computed by `metric`.
+    /// The iterator is lazy and uses no memory besides the traversal stack.
+    pub fn within_radius<M: Metric<C, K>>(
+        &self,
+        location: &[C; K],
+        radius: M::Distance,
+        metric: &M,
+    ) -> impl Iterator<Item = (M::Distance, &[C; K], &V)> {
+        self.find_indices_within_radius(location, radius, metric)
+            .map(|(d, i)| self.neighbour(d, i))
+    }
+
+    fn find_indices_within_radius<M: Metric<C, K>>(
+        &self,
+        location: &[C; K],
+        radius: M::Distance,
+        metric: &M,
+    ) -> impl Iterator<Item = (M::Distance, usize)> {
+        let mut stack = Vec::new();
+        stack.extend(self.root());
+        // The nodes that are checked before visiting the next subtree.
+        let mut pending = 0..0;
+        core::iter::from_fn(move || {
+            loop {
+                for i in pending.by_ref() {
+                    let d = metric.distance(&self.nodes[i].location, location);
+                    if d <= radius {
+                        return Some((d, i));
+                    }
+                }
+                let subtree = stack.pop()?;
+                let Some([near, far]) = self.near_far(subtree, location) else {
+                    pending = subtree.range();
+                    continue;
+                };
+                if let Some(far) = far
+                    && metric.axis_lower_bound(
+                        location,
+                        subtree.coord_index,
+                        self.split_value(subtree.index, subtree.coord_index),
+                    ) <= radius
+                {
+                    stack.push(far);
+                }
+                stack.extend(near);
+                pending = subtree.index..subtree.index + 1;
             }
-            if greater && node.greater_index != EMPTY {
-                queue.push_back((
-                    next_coord_index,
-                    &self.nodes[(node.greater_index - 1) as usize],
-                ));
+        })
+    }
+
+    /// Returns an iterator over nodes that lie inside the rectangle with corners `min` and `max`.
+    ///
+    /// The boundary of the rectangle is inclusive.
+    /// The iterator is lazy: the tree is traversed as the nodes are consumed, and the subtrees that
+    /// lie outside of the rectangle are skipped.
+    pub fn find_in_rect(&self, min: [C; K], max: [C; K]) -> impl Iterator<Item = (&[C; K], &V)> {
+        self.find_indices_in_rect(min, max).map(|i| {
+            let node = &self.nodes[i];
+            (&node.location, &node.value)
+        })
+    }
+
+    fn find_indices_in_rect(&self, min: [C; K], max: [C; K]) -> impl Iterator<Item = usize> {
+        let mut stack = Vec::new();
+        stack.extend(self.root());
+        // The nodes that are checked before visiting the next subtree.
+        let mut pending = 0..0;
+        core::iter::from_fn(move || {
+            loop {
+                for i in pending.by_ref() {
+                    let location = &self.nodes[i].location;
+                    let inside = (0..K).all(|j| min[j] <= location[j] && location[j] <= max[j]);
+                    if inside {
+                        return Some(i);
+                    }
+                }
+                let subtree = stack.pop()?;
+                let Some([lesser, greater]) = self.children(subtree) else {
+                    pending = subtree.range();
+                    continue;
+                };
+                let coord_index = subtree.coord_index;
+                let split_value = self.split_value(subtree.index, coord_index);
+                if let Some(greater) = greater
+                    && split_value <= max[coord_index]
+                {
+                    stack.push(greater);
+                }
+                if let Some(lesser) = lesser
+                    && min[coord_index] <= split_value
+                {
+                    stack.push(lesser);
+                }
+                pending = subtree.index..subtree.index + 1;
             }
-        }
-        neighbours
+        })
     }
 
     /// Returns an iterator over nodes.
-    pub fn iter(&self) -> impl Iterator<Item = (&[C; 2], &V)> {
+    pub fn iter(&self) -> impl Iterator<Item = (&[C; K], &V)> {
         self.nodes.iter().map(|node| (&node.location, &node.value))
     }
 
+    /// Returns the node with the specified `index` or `None` if the index is out of bounds.
+    ///
+    /// The index is the position of the node in [`iter`](Self::iter).
+    pub fn get(&self, index: usize) -> Option<(&[C; K], &V)> {
+        self.nodes
+            .get(index)
+            .map(|node| (&node.location, &node.value))
+    }
+
     /// Returns the number of nodes.
     pub fn len(&self) -> usize {
         self.nodes.len()
@@ -184,13 +1180,154 @@ impl<C: Ord + Copy + Default, V: Default> Tree2D<C, V> {
     pub fn is_empty(&self) -> bool {
         self.nodes.is_empty()
     }
+
+    /// Moves the nodes out of the tree.
+    fn into_nodes(self) -> impl Iterator<Item = ([C; K], V)> {
+        self.nodes
+            .into_iter()
+            .map(|node| (node.location, node.value))
+    }
+}
+
+impl<C: Ord + Copy, V, const K: usize> Nodes<C, K> for TreeKD<C, V, K> {
+    fn root(&self) -> Option<Subtree> {
+        (!self.nodes.is_empty()).then_some(Subtree {
+            coord_index: 0,
+            index: 0,
+            len: self.nodes.len(),
+        })
+    }
+
+    fn children(&self, subtree: Subtree) -> Option<[Option<Subtree>; 2]> {
+        self.shape
+            .children::<K>(subtree, |index| self.links.get(index))
+    }
+
+    fn bucket(&self, subtree: Subtree) -> Range<usize> {
+        subtree.range()
+    }
+
+    fn split_value(&self, index: usize, coord_index: usize) -> C {
+        self.nodes[index].location[coord_index]
+    }
+}
+
+/// Returns the number of split nodes in the tree with `len` nodes.
+fn num_splits(len: usize, leaf_size: usize) -> usize {
+    // The sizes of the subtrees at the same depth differ by at most one, hence it's enough to
+    // count the subtrees of size `n` and `n + 1` at each depth.
+    let mut n = len;
+    let mut counts = [1_usize, 0];
+    let mut num_splits = 0;
+    while n + 1 > leaf_size && counts != [0, 0] {
+        // `n >= leaf_size >= 1`
+        let next_n = (n - 1) / 2;
+        let mut next_counts = [0, 0];
+        for (size, count) in [(n, counts[0]), (n + 1, counts[1])] {
+            if size <= leaf_size {
+                continue;
+            }
+            num_splits += count;
+            let lesser_len = size / 2;
+            for child_len in [lesser_len, size - lesser_len - 1] {
+                next_counts[child_len - next_n] += count;
+            }
+        }
+        n = next_n;
+        counts = next_counts;
+    }
+    num_splits
+}
+
+/// Recursively moves the split node to the position determined by the `shape`, the nodes that are
+/// behind the splitting plane to the left and the nodes in front of the plane to the right of it.
+///
+/// The nodes in the leaf buckets are sorted.
+fn partition<C: Ord, V: Ord, const K: usize>(
+    nodes: &mut [([C; K], V)],
+    coord_index: usize,
+    shape: &Shape,
+    slot: usize,
+) {
+    let Some((lesser_len, [lesser_slot, greater_slot])) = shape.split(slot, nodes.len()) else {
+        nodes.sort_unstable_by(|a, b| compare_nodes(a, b, coord_index));
+        return;
+    };
+    let (lesser_nodes, _, greater_nodes) = select_nth(nodes, lesser_len, coord_index);
+    let next_coord_index = (coord_index + 1) % K;
+    partition(lesser_nodes, next_coord_index, shape, lesser_slot);
+    partition(greater_nodes, next_coord_index, shape, greater_slot);
+}
+
+/// Parallel version of [`partition`].
+#[cfg(feature = "rayon")]
+fn par_partition<C: Ord + Send, V: Ord + Send, const K: usize>(
+    nodes: &mut [([C; K], V)],
+    coord_index: usize,
+    shape: &Shape,
+    slot: usize,
+) {
+    // Spawning tasks for small slices is slower than partitioning them sequentially.
+    const MIN_PARALLEL_LEN: usize = 4096;
+    if nodes.len() < MIN_PARALLEL_LEN {
+        return partition(nodes, coord_index, shape, slot);
+    }
+    let Some((lesser_len, [lesser_slot, greater_slot])) = shape.split(slot, nodes.len()) else {
+        return partition(nodes, coord_index, shape, slot);
+    };
+    let (lesser_nodes, _, greater_nodes) = select_nth(nodes, lesser_len, coord_index);
+    let next_coord_index = (coord_index + 1) % K;
+    rayon::join(
+        || par_partition(lesser_nodes, next_coord_index, shape, lesser_slot),
+        || par_partition(greater_nodes, next_coord_index, shape, greater_slot),
+    );
+}
+
+#[allow(clippy::type_complexity)]
+fn select_nth<C: Ord, V: Ord, const K: usize>(
+    nodes: &mut [([C; K], V)],
+    n: usize,
+    coord_index: usize,
+) -> (&mut [([C; K], V)], &mut ([C; K], V), &mut [([C; K], V)]) {
+    nodes.select_nth_unstable_by(n, |a, b| compare_nodes(a, b, coord_index))
+}
+
+/// Compares the nodes by the coordinate with index `coord_index`, then by the following coordinates
+/// (wrapping around), and then by the value.
+///
+/// This is a total order, hence the split node is the same regardless of the order of the nodes.
+fn compare_nodes<C: Ord, V: Ord, const K: usize>(
+    a: &([C; K], V),
+    b: &([C; K], V),
+    coord_index: usize,
+) -> Ordering {
+    a.0[coord_index].cmp(&b.0[coord_index]).then_with(|| {
+        (coord_index + 1..K)
+            .chain(0..coord_index)
+            .map(|i| a.0[i].cmp(&b.0[i]))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.1.cmp(&b.1))
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::EuclideanDistanceSquared;
+    use crate::OrthogonalDistance;
     use crate::euclidean_distance_squared;
+    use crate::orthogonal_distance;
     use alloc::vec;
+    use arbitrary::Unstructured;
+    use arbtest::arbtest;
+    #[cfg(feature = "std")]
+    use testing::arbitrary_geo_location;
+    #[cfg(feature = "std")]
+    use testing::arbitrary_geo_tree;
+    use testing::arbitrary_location;
+    use testing::arbitrary_options;
+    use testing::arbitrary_tree;
 
     #[test]
     fn tree_works() {
@@ -204,4 +1341,627 @@ mod tests {
         let neighbours = tree.find_nearest(&[5, 0], 25_u64, 1, euclidean_distance_squared);
         assert_eq!(vec![(4, &[3, 0], &())], neighbours);
     }
+
+    #[test]
+    fn tree_kd_matches_brute_force() {
+        fn check<const K: usize>(u: &mut Unstructured<'_>) -> arbitrary::Result<()> {
+            let tree = arbitrary_tree::<u32, K>(u, 100)?;
+            let location = arbitrary_location(u, 120)?;
+            let max_neighbours = u.int_in_range(0..=20)?;
+            let max_distance = u.int_in_range(0..=30_000)?;
+            check_find_nearest(
+                &tree,
+                &location,
+                max_distance,
+                max_neighbours,
+                &OrthogonalDistance,
+            );
+            check_find_nearest(
+                &tree,
+                &location,
+                max_distance,
+                max_neighbours,
+                &EuclideanDistanceSquared,
+            );
+            let actual: Vec<_> = tree
+                .find_nearest(&location, max_distance, max_neighbours, |a, b| {
+                    EuclideanDistanceSquared.distance(a, b)
+                })
+                .into_iter()
+                .map(|(d, ..)| d)
+                .collect();
+            let expected = brute_force(
+                &tree,
+                &location,
+                max_distance,
+                max_neighbours,
+                &EuclideanDistanceSquared,
+            );
+            assert_eq!(expected, actual);
+            Ok(())
+        }
+        arbtest(|u| {
+            check::<1>(u)?;
+            check::<2>(u)?;
+            check::<3>(u)?;
+            Ok(())
+        });
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_from_nodes_matches_from_nodes() {
+        arbtest(|u| {
+            let n = u.int_in_range(0..=20_000)?;
+            let mut nodes = Vec::with_capacity(n);
+            for _ in 0..n {
+                nodes.push((arbitrary_location::<3>(u, 1000)?, u.arbitrary::<u32>()?));
+            }
+            let options = arbitrary_options(u)?;
+            let expected = TreeKD::from_nodes_with_options(nodes.clone(), options);
+            let actual = TreeKD::par_from_nodes_with_options(nodes, options);
+            assert_eq!(expected, actual);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn merge_works() {
+        arbtest(|u| {
+            let trees: Vec<Tree2D<i64, u32>> = (0..u.int_in_range(0..=4)?)
+                .map(|_| arbitrary_tree(u, 100))
+                .collect::<Result<_, _>>()?;
+            let mut expected: Vec<_> = trees
+                .iter()
+                .flat_map(|tree| tree.iter().map(|(l, v)| (*l, *v)))
+                .collect();
+            let tree = match u.arbitrary()? {
+                true => Tree2D::merge_all(trees),
+                false => trees
+                    .into_iter()
+                    .reduce(Tree2D::merge)
+                    .unwrap_or_else(|| Tree2D::from_nodes(Vec::new())),
+            };
+            let mut actual: Vec<_> = tree.iter().map(|(l, v)| (*l, *v)).collect();
+            actual.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(expected, actual);
+            let location = arbitrary_location(u, 120)?;
+            check_find_nearest(&tree, &location, u64::MAX, 10, &EuclideanDistanceSquared);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn num_splits_works() {
+        fn num_splits_recursive(len: usize, leaf_size: usize) -> usize {
+            if len <= leaf_size {
+                return 0;
+            }
+            let lesser_len = len / 2;
+            1 + num_splits_recursive(lesser_len, leaf_size)
+                + num_splits_recursive(len - lesser_len - 1, leaf_size)
+        }
+        arbtest(|u| {
+            let len = u.int_in_range(0..=10_000)?;
+            let leaf_size = u.int_in_range(1..=100)?;
+            assert_eq!(
+                num_splits_recursive(len, leaf_size),
+                num_splits(len, leaf_size),
+                "len = {len}, leaf_size = {leaf_size}"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn leaf_buckets_visit_few_nodes() {
+        for layout in [Layout::Explicit, Layout::Implicit, Layout::VanEmdeBoas] {
+            let options = TreeOptions::new().leaf_size(16).layout(layout);
+            let tree = Tree2D::from_nodes_with_options(grid(100), options);
+            assert!(tree.links.len() < tree.len() / 8, "{}", tree.links.len());
+            let mut stats = SearchStats::default();
+            let neighbours = tree.find_nearest_with_stats(
+                &[50, 50],
+                u64::MAX,
+                10,
+                &EuclideanDistanceSquared,
+                &mut stats,
+            );
+            assert_eq!(10, neighbours.len());
+            assert!(stats.visited_nodes < 200, "stats = {stats:?}");
+        }
+    }
+
+    #[test]
+    fn find_nearest_visits_few_nodes() {
+        for layout in [Layout::Explicit, Layout::Implicit, Layout::VanEmdeBoas] {
+            let options = TreeOptions::new().layout(layout);
+            let tree = Tree2D::from_nodes_with_options(grid(100), options);
+            let mut stats = SearchStats::default();
+            let neighbours = tree.find_nearest_with_stats(
+                &[50, 50],
+                u64::MAX,
+                10,
+                &EuclideanDistanceSquared,
+                &mut stats,
+            );
+            assert_eq!(10, neighbours.len());
+            assert!(stats.visited_nodes < 100, "stats = {stats:?}");
+        }
+    }
+
+    #[test]
+    fn find_nearest_computes_few_distances() {
+        for layout in [Layout::Explicit, Layout::Implicit, Layout::VanEmdeBoas] {
+            let options = TreeOptions::new().layout(layout);
+            let tree = Tree2D::from_nodes_with_options(grid(100), options);
+            let mut num_calls = 0;
+            let neighbours = tree.find_nearest(&[50, 50], u64::MAX, 10, |a, b| {
+                num_calls += 1;
+                euclidean_distance_squared(a, b)
+            });
+            assert_eq!(10, neighbours.len());
+            assert!(num_calls < tree.len() / 10, "num_calls = {num_calls}");
+        }
+    }
+
+    #[test]
+    fn find_nearest_works_with_unlimited_neighbours() {
+        let tree = Tree2D::from_nodes(grid(10));
+        let location = [5, 5];
+        let metric = &EuclideanDistanceSquared;
+        let expected = tree.find_nearest_by_metric(&location, u64::MAX, 100, metric);
+        assert_eq!(100, expected.len());
+        assert_eq!(
+            expected,
+            tree.find_nearest_by_metric(&location, u64::MAX, usize::MAX, metric)
+        );
+        assert_eq!(
+            expected,
+            tree.find_nearest_where(&location, u64::MAX, usize::MAX, metric, |_, _| true)
+        );
+        assert_eq!(
+            expected,
+            tree.find_nearest(&location, u64::MAX, usize::MAX, euclidean_distance_squared)
+        );
+    }
+
+    #[test]
+    fn huge_leaf_size_works() {
+        for layout in [Layout::Explicit, Layout::Implicit, Layout::VanEmdeBoas] {
+            let options = TreeOptions::new().leaf_size(usize::MAX).layout(layout);
+            let tree = Tree2D::from_nodes_with_options(grid(10), options);
+            let neighbours =
+                tree.find_nearest_by_metric(&[5, 5], u64::MAX, 3, &EuclideanDistanceSquared);
+            assert_eq!(3, neighbours.len());
+        }
+    }
+
+    #[test]
+    fn get_works() {
+        let tree = Tree2D::from_nodes(vec![([1, 2], 'a'), ([3, 4], 'b')]);
+        for (i, node) in tree.iter().enumerate() {
+            assert_eq!(Some(node), tree.get(i));
+        }
+        assert_eq!(None, tree.get(2));
+    }
+
+    #[test]
+    fn implicit_layout_has_no_links() {
+        let options = TreeOptions::new().layout(Layout::Implicit);
+        let tree = Tree2D::from_nodes_with_options(grid(100), options);
+        assert_eq!(Links::Narrow(Vec::new()), tree.links);
+        assert_eq!(10_000, tree.len());
+    }
+
+    #[test]
+    fn wide_links_match_narrow_links() {
+        arbtest(|u| {
+            let mut nodes: Vec<([i64; 2], u32)> = u.arbitrary()?;
+            let leaf_size = u.int_in_range(1..=16)?;
+            let layout = *u.choose(&[Layout::Explicit, Layout::VanEmdeBoas])?;
+            let options = TreeOptions::new().leaf_size(leaf_size).layout(layout);
+            let shape = Shape::new(nodes.len(), options);
+            partition(&mut nodes, 0, &shape, 0);
+            let (narrow_nodes, narrow_links) =
+                Tree2D::explicit_layout::<u32>(nodes.clone(), leaf_size, layout);
+            let (wide_nodes, wide_links) = Tree2D::explicit_layout::<u64>(nodes, leaf_size, layout);
+            assert_eq!(narrow_nodes, wide_nodes);
+            let narrow_links: Vec<_> = narrow_links
+                .into_iter()
+                .map(|link| Link {
+                    lesser_index: u64::from(link.lesser_index),
+                    greater_index: u64::from(link.greater_index),
+                })
+                .collect();
+            assert_eq!(narrow_links, wide_links);
+            Ok(())
+        });
+    }
+
+    fn grid(n: i64) -> Vec<([i64; 2], ())> {
+        let mut nodes = Vec::new();
+        for x in 0..n {
+            for y in 0..n {
+                nodes.push(([x, y], ()));
+            }
+        }
+        nodes
+    }
+
+    #[test]
+    fn find_nearest_matches_brute_force() {
+        arbtest(|u| {
+            let tree: Tree2D<i64, u32> = arbitrary_tree(u, 100)?;
+            let location = [u.int_in_range(-120..=120)?, u.int_in_range(-120..=120)?];
+            let max_neighbours = u.int_in_range(0..=20)?;
+            let max_distance = u.int_in_range(0..=30_000)?;
+            for calc_distance in [euclidean_distance_squared, orthogonal_distance] {
+                let actual: Vec<_> = tree
+                    .find_nearest(&location, max_distance, max_neighbours, calc_distance)
+                    .into_iter()
+                    .map(|(d, ..)| d)
+                    .collect();
+                let mut expected: Vec<_> = tree
+                    .iter()
+                    .map(|(other, _)| calc_distance(other, &location))
+                    .filter(|d| *d <= max_distance)
+                    .collect();
+                expected.sort_unstable();
+                expected.truncate(max_neighbours);
+                assert_eq!(expected, actual, "location = {location:?}, tree = {tree:?}");
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn find_nearest_by_metric_matches_brute_force() {
+        arbtest(|u| {
+            let tree = arbitrary_tree(u, 100)?;
+            let location = [u.int_in_range(-120..=120)?, u.int_in_range(-120..=120)?];
+            let max_neighbours = u.int_in_range(0..=20)?;
+            let max_distance = u.int_in_range(0..=30_000)?;
+            check_find_nearest(
+                &tree,
+                &location,
+                max_distance,
+                max_neighbours,
+                &OrthogonalDistance,
+            );
+            check_find_nearest(
+                &tree,
+                &location,
+                max_distance,
+                max_neighbours,
+                &EuclideanDistanceSquared,
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn find_nearest_approx_is_within_bound() {
+        arbtest(|u| {
+            let tree = arbitrary_tree(u, 100)?;
+            let location = [u.int_in_range(-120..=120)?, u.int_in_range(-120..=120)?];
+            let max_neighbours = u.int_in_range(0..=20)?;
+            let epsilon = *u.choose(&[0.0, 0.1, 0.5, 1.0, 3.0])?;
+            check_find_nearest_approx(
+                &tree,
+                &location,
+                max_neighbours,
+                epsilon,
+                &OrthogonalDistance,
+            );
+            check_find_nearest_approx(
+                &tree,
+                &location,
+                max_neighbours,
+                epsilon,
+                &EuclideanDistanceSquared,
+            );
+            Ok(())
+        });
+    }
+
+    fn check_find_nearest_approx<M: Metric<i64, Distance = u64>>(
+        tree: &Tree2D<i64, u32>,
+        location: &[i64; 2],
+        max_neighbours: usize,
+        epsilon: f64,
+        metric: &M,
+    ) {
+        let actual: Vec<_> = tree
+            .find_nearest_approx(location, u64::MAX, max_neighbours, epsilon, metric)
+            .into_iter()
+            .map(|(d, ..)| d)
+            .collect();
+        let expected = brute_force(tree, location, u64::MAX, max_neighbours, metric);
+        assert_eq!(expected.len(), actual.len());
+        for (expected, actual) in expected.iter().zip(actual.iter()) {
+            assert!(
+                *actual as f64 <= *expected as f64 * (1.0 + epsilon),
+                "expected = {expected}, actual = {actual}, epsilon = {epsilon}"
+            );
+        }
+        if epsilon == 0.0 {
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn find_nearest_approx_visits_fewer_nodes() {
+        let tree = Tree2D::from_nodes(grid(100));
+        let mut visited_nodes = Vec::new();
+        for epsilon in [0.0, 1.0, 10.0] {
+            let mut stats = SearchStats::default();
+            let neighbours = tree.find_nearest_approx_with_stats(
+                &[50, 50],
+                u64::MAX,
+                10,
+                epsilon,
+                &EuclideanDistanceSquared,
+                &mut stats,
+            );
+            assert_eq!(10, neighbours.len());
+            visited_nodes.push(stats.visited_nodes);
+        }
+        assert!(
+            visited_nodes.windows(2).all(|w| w[0] > w[1]),
+            "visited nodes = {visited_nodes:?}"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn find_nearest_by_earth_distance_matches_brute_force() {
+        arbtest(|u| {
+            let tree = arbitrary_geo_tree(u)?;
+            let location = arbitrary_geo_location(u)?;
+            let max_neighbours = u.int_in_range(0..=20)?;
+            let max_distance = u.int_in_range(0..=10_000_000)?;
+            check_find_nearest(
+                &tree,
+                &location,
+                max_distance,
+                max_neighbours,
+                &crate::EarthDistance,
+            );
+            Ok(())
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn find_nearest_across_antimeridian() {
+        const DEGREE: i64 = 1_000_000_000;
+        arbtest(|u| {
+            let latitude = u.int_in_range(-80 * DEGREE..=80 * DEGREE)?;
+            let sign = if u.arbitrary()? { 1 } else { -1 };
+            // The village is on the other side of the antimeridian, a few hundred metres away.
+            let location = [sign * (180 * DEGREE - DEGREE / 1000), latitude];
+            let village = [-sign * (180 * DEGREE - DEGREE / 1000), latitude];
+            let mut nodes = vec![(village, 0)];
+            // Other nodes are on the same side as the location but farther away.
+            let num_nodes = u.arbitrary_len::<[i64; 2]>()?;
+            for _ in 0..num_nodes {
+                let longitude = u.int_in_range(-180 * DEGREE..=(180 - 1) * DEGREE)?;
+                let latitude = u.int_in_range(-90 * DEGREE..=90 * DEGREE)?;
+                nodes.push(([sign * longitude, latitude], 1));
+            }
+            let tree = Tree2D::from_nodes(nodes);
+            let metric = crate::EarthDistance;
+            let (d, nearest, _) = tree
+                .find_nearest_one(&location, u64::MAX, &metric)
+                .expect("Should be found");
+            assert_eq!(&village, nearest, "d = {d}");
+            assert!(d < 1000, "d = {d}");
+            let neighbours = tree.find_nearest_by_metric(&location, 1000, 10, &metric);
+            assert_eq!(vec![(d, &village, &0)], neighbours);
+            assert_eq!(
+                Some(&village),
+                tree.iter_nearest(&location, &metric)
+                    .map(|(_, l, _)| l)
+                    .next()
+            );
+            assert_eq!(
+                vec![&village],
+                tree.within_radius(&location, 1000, &metric)
+                    .map(|(_, l, _)| l)
+                    .collect::<Vec<_>>()
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn find_in_rect_matches_brute_force() {
+        arbtest(|u| {
+            let tree: Tree2D<i64, u32> = arbitrary_tree(u, 100)?;
+            let a: [i64; 2] = [u.int_in_range(-120..=120)?, u.int_in_range(-120..=120)?];
+            let b: [i64; 2] = [u.int_in_range(-120..=120)?, u.int_in_range(-120..=120)?];
+            let min = [a[0].min(b[0]), a[1].min(b[1])];
+            let max = [a[0].max(b[0]), a[1].max(b[1])];
+            let mut actual: Vec<_> = tree.find_in_rect(min, max).collect();
+            actual.sort_unstable();
+            let mut expected: Vec<_> = tree
+                .iter()
+                .filter(|(location, _)| {
+                    (0..2).all(|j| min[j] <= location[j] && location[j] <= max[j])
+                })
+                .collect();
+            expected.sort_unstable();
+            assert_eq!(
+                expected, actual,
+                "min = {min:?}, max = {max:?}, tree = {tree:?}"
+            );
+            Ok(())
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn find_nearest_near_poles_matches_brute_force() {
+        const DEGREE: i64 = 1_000_000_000;
+        arbtest(|u| {
+            let sign = if u.arbitrary()? { 1 } else { -1 };
+            let arbitrary_polar_location =
+                |u: &mut Unstructured<'_>| -> arbitrary::Result<[i64; 2]> {
+                    let longitude = u.int_in_range(-180 * DEGREE..=180 * DEGREE)?;
+                    let latitude = u.int_in_range(85 * DEGREE..=90 * DEGREE)?;
+                    Ok([longitude, sign * latitude])
+                };
+            let location = arbitrary_polar_location(u)?;
+            let num_nodes = u.arbitrary_len::<[i64; 2]>()?;
+            let mut nodes = Vec::with_capacity(num_nodes);
+            for i in 0..num_nodes {
+                nodes.push((arbitrary_polar_location(u)?, i as u32));
+            }
+            let tree = Tree2D::from_nodes(nodes);
+            let max_neighbours = u.int_in_range(0..=20)?;
+            let max_distance = u.int_in_range(0..=1_000_000)?;
+            check_find_nearest(
+                &tree,
+                &location,
+                max_distance,
+                max_neighbours,
+                &crate::EarthDistance,
+            );
+            Ok(())
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn find_nearest_across_pole() {
+        const DEGREE: i64 = 1_000_000_000;
+        for sign in [1, -1] {
+            for longitude in [-180, -135, -90, -1, 0, 1, 45, 90, 179] {
+                let latitude = sign * (90 * DEGREE - DEGREE / 100);
+                let location = [longitude * DEGREE, latitude];
+                // The station is on the opposite meridian, ~2 km away across the pole.
+                let opposite_longitude = if longitude > 0 {
+                    longitude - 180
+                } else {
+                    longitude + 180
+                };
+                let station = [opposite_longitude * DEGREE, latitude];
+                // Other nodes are on the same meridian, but farther away from the pole.
+                let mut nodes = vec![(station, 0)];
+                for i in 1..100 {
+                    let latitude = sign * (90 * DEGREE - DEGREE / 100 - i * DEGREE / 20);
+                    nodes.push(([longitude * DEGREE, latitude], i as u32));
+                    let jitter = (longitude + i % 7 - 3).clamp(-180, 180);
+                    nodes.push(([jitter * DEGREE, latitude], i as u32));
+                }
+                let tree = Tree2D::from_nodes(nodes);
+                let metric = crate::EarthDistance;
+                let (d, nearest, _) = tree
+                    .find_nearest_one(&location, u64::MAX, &metric)
+                    .expect("Should be found");
+                assert_eq!(&station, nearest, "location = {location:?}, d = {d}");
+                assert!(d < 2500, "d = {d}");
+                let neighbours = tree.find_nearest_by_metric(&location, u64::MAX, 3, &metric);
+                assert_eq!(&station, neighbours[0].1);
+            }
+        }
+    }
+
+    fn check_find_nearest<M: Metric<i64, K>, const K: usize>(
+        tree: &TreeKD<i64, u32, K>,
+        location: &[i64; K],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+    ) where
+        M::Distance: core::fmt::Debug,
+    {
+        let actual: Vec<_> = tree
+            .find_nearest_by_metric(location, max_distance, max_neighbours, metric)
+            .into_iter()
+            .map(|(d, ..)| d)
+            .collect();
+        let expected = brute_force(tree, location, max_distance, max_neighbours, metric);
+        assert_eq!(expected, actual, "location = {location:?}, tree = {tree:?}");
+        let actual = tree.find_nearest_one(location, max_distance, metric);
+        let expected = brute_force(tree, location, max_distance, 1, metric);
+        assert_eq!(
+            expected.first(),
+            actual.as_ref().map(|(d, ..)| d),
+            "location = {location:?}, tree = {tree:?}"
+        );
+        if let Some((d, other, _)) = actual {
+            assert_eq!(d, metric.distance(other, location));
+        }
+        let mut actual: Vec<_> = tree
+            .within_radius(location, max_distance, metric)
+            .map(|(d, ..)| d)
+            .collect();
+        actual.sort_unstable();
+        let expected = brute_force(tree, location, max_distance, usize::MAX, metric);
+        assert_eq!(expected, actual, "location = {location:?}, tree = {tree:?}");
+        let actual: Vec<_> = tree
+            .iter_nearest(location, metric)
+            .map(|(d, ..)| d)
+            .take_while(|d| *d <= max_distance)
+            .collect();
+        assert_eq!(expected, actual, "location = {location:?}, tree = {tree:?}");
+        assert_eq!(tree.len(), tree.iter_nearest(location, metric).count());
+        let actual: Vec<_> = tree
+            .find_nearest_where(
+                location,
+                max_distance,
+                max_neighbours,
+                metric,
+                |_, value| value % 3 == 0,
+            )
+            .into_iter()
+            .map(|(d, _, value)| {
+                assert_eq!(0, value % 3);
+                d
+            })
+            .collect();
+        let expected = brute_force_where(
+            tree,
+            location,
+            max_distance,
+            max_neighbours,
+            metric,
+            |value| value % 3 == 0,
+        );
+        assert_eq!(expected, actual, "location = {location:?}, tree = {tree:?}");
+    }
+
+    fn brute_force<M: Metric<i64, K>, const K: usize>(
+        tree: &TreeKD<i64, u32, K>,
+        location: &[i64; K],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+    ) -> Vec<M::Distance> {
+        brute_force_where(tree, location, max_distance, max_neighbours, metric, |_| {
+            true
+        })
+    }
+
+    fn brute_force_where<M: Metric<i64, K>, const K: usize>(
+        tree: &TreeKD<i64, u32, K>,
+        location: &[i64; K],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+        predicate: impl Fn(&u32) -> bool,
+    ) -> Vec<M::Distance> {
+        let mut distances: Vec<_> = tree
+            .iter()
+            .filter(|(_, value)| predicate(value))
+            .map(|(other, _)| metric.distance(other, location))
+            .filter(|d| *d <= max_distance)
+            .collect();
+        distances.sort_unstable();
+        distances.truncate(max_neighbours);
+        distances
+    }
 }
@@ -0,0 +1,325 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::Scratch;
+use super::SearchStats;
+use super::TreeKD;
+use crate::Metric;
+
+/// Options that control [`TreeKD::find_nearest_batch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BatchOptions {
+    sort: bool,
+}
+
+impl BatchOptions {
+    /// Create default options.
+    pub const fn new() -> Self {
+        Self { sort: true }
+    }
+
+    /// Sets whether the queries are sorted by their Morton code before the search.
+    ///
+    /// Consecutive queries that are close to each other visit the same nodes of the tree,
+    /// i.e. sorting improves cache locality when the locations are in random order.
+    /// The results are returned in the original order regardless of this option.
+    /// The default is `true`.
+    pub const fn sort(mut self, sort: bool) -> Self {
+        self.sort = sort;
+        self
+    }
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The results of [`TreeKD::find_nearest_batch`].
+///
+/// The neighbours of all locations are stored in a single vector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchResults<T> {
+    items: Vec<T>,
+    /// The range of items for each location.
+    ranges: Vec<Range<usize>>,
+}
+
+impl<T> BatchResults<T> {
+    /// Returns the neighbours of the location with index `i`.
+    pub fn get(&self, i: usize) -> Option<&[T]> {
+        self.ranges.get(i).map(|range| &self.items[range.clone()])
+    }
+
+    /// Returns an iterator over the neighbours of each location.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &[T]> {
+        self.ranges.iter().map(|range| &self.items[range.clone()])
+    }
+
+    /// Returns the number of locations.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Returns `true` if there are no locations.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Moves the results of the search for `locations[indices[j]]` from position `j` to
+    /// position `indices[j]`.
+    fn reorder(mut self, indices: &[usize]) -> Self {
+        let mut ranges = vec![0..0; self.ranges.len()];
+        for (i, range) in indices.iter().zip(self.ranges.drain(..)) {
+            ranges[*i] = range;
+        }
+        self.ranges = ranges;
+        self
+    }
+
+    /// Appends `other` results to the end.
+    #[cfg(feature = "rayon")]
+    fn append(&mut self, other: Self) {
+        let offset = self.items.len();
+        self.items.extend(other.items);
+        self.ranges.extend(
+            other
+                .ranges
+                .into_iter()
+                .map(|range| range.start + offset..range.end + offset),
+        );
+    }
+}
+
+impl<T> core::ops::Index<usize> for BatchResults<T> {
+    type Output = [T];
+
+    fn index(&self, i: usize) -> &Self::Output {
+        &self.items[self.ranges[i].clone()]
+    }
+}
+
+impl<V: Default, const K: usize> TreeKD<i64, V, K> {
+    /// Returns up to `max_neighbours` nodes within `max_distance` that are closest to each of
+    /// the `locations`.
+    ///
+    /// This is the same as calling [`find_nearest_by_metric`](Self::find_nearest_by_metric) for
+    /// each location, but the memory buffers are allocated once for the whole batch,
+    /// and the queries are optionally reordered to improve cache locality
+    /// (see [`BatchOptions::sort`]).
+    pub fn find_nearest_batch<M: Metric<i64, K>>(
+        &self,
+        locations: &[[i64; K]],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+        options: BatchOptions,
+    ) -> BatchResults<(M::Distance, &[i64; K], &V)> {
+        let order = query_order(locations, options);
+        self.find_nearest_ordered(locations, &order, max_distance, max_neighbours, metric)
+            .reorder(&order)
+    }
+
+    /// The same as [`find_nearest_batch`](Self::find_nearest_batch) but the locations are
+    /// searched in parallel using [`rayon`].
+    #[cfg(feature = "rayon")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rayon")))]
+    pub fn par_find_nearest_batch<M>(
+        &self,
+        locations: &[[i64; K]],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+        options: BatchOptions,
+    ) -> BatchResults<(M::Distance, &[i64; K], &V)>
+    where
+        M: Metric<i64, K> + Sync,
+        M::Distance: Send + Sync,
+        V: Sync,
+    {
+        use rayon::prelude::*;
+        // The number of queries that share the memory buffers.
+        const CHUNK_LEN: usize = 1024;
+        let order = query_order(locations, options);
+        order
+            .par_chunks(CHUNK_LEN)
+            .map(|indices| {
+                self.find_nearest_ordered(locations, indices, max_distance, max_neighbours, metric)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .fold(
+                BatchResults {
+                    items: Vec::new(),
+                    ranges: Vec::with_capacity(locations.len()),
+                },
+                |mut all, results| {
+                    all.append(results);
+                    all
+                },
+            )
+            .reorder(&order)
+    }
+
+    /// Searches the neighbours of `locations[indices[0]]`, `locations[indices[1]]` etc.
+    ///
+    /// The results are stored in the order of `indices`.
+    fn find_nearest_ordered<M: Metric<i64, K>>(
+        &self,
+        locations: &[[i64; K]],
+        indices: &[usize],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+    ) -> BatchResults<(M::Distance, &[i64; K], &V)> {
+        let mut items = Vec::new();
+        let mut ranges = Vec::with_capacity(indices.len());
+        let mut scratch = Scratch::new();
+        let mut stats = SearchStats::default();
+        for i in indices.iter() {
+            let location = &locations[*i];
+            let start = items.len();
+            if max_neighbours == 1 {
+                items.extend(self.find_nearest_one(location, max_distance, metric));
+            } else {
+                self.find_nearest_impl(
+                    location,
+                    max_distance,
+                    max_neighbours,
+                    |other, _| Some(metric.distance(other, location)),
+                    |axis, split_value| metric.axis_lower_bound(location, axis, split_value),
+                    &mut stats,
+                    &mut scratch,
+                    |d, i| items.push(self.neighbour(d, i)),
+                );
+            }
+            ranges.push(start..items.len());
+        }
+        BatchResults { items, ranges }
+    }
+}
+
+/// Returns the indices of the `locations` in the order in which they are searched.
+fn query_order<const K: usize>(locations: &[[i64; K]], options: BatchOptions) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..locations.len()).collect();
+    if options.sort {
+        order.sort_unstable_by_key(|i| morton_code(&locations[*i]));
+    }
+    order
+}
+
+/// Returns Z-order curve index of the `location`.
+///
+/// The most significant bits of the coordinates are interleaved; the rest are discarded.
+fn morton_code<const K: usize>(location: &[i64; K]) -> u64 {
+    let bits_per_coord = u64::BITS as usize / K;
+    let mut code = 0_u64;
+    for bit in (u64::BITS as usize - bits_per_coord..u64::BITS as usize).rev() {
+        for coord in location.iter() {
+            // Flip the sign bit to preserve the order of negative and positive coordinates.
+            let coord = (*coord as u64) ^ (1 << 63);
+            code = (code << 1) | ((coord >> bit) & 1);
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanDistanceSquared;
+    use crate::Tree2D;
+    use crate::tree::testing::arbitrary_options;
+    use arbtest::arbtest;
+
+    #[test]
+    fn find_nearest_batch_matches_find_nearest() {
+        arbtest(|u| {
+            let nodes: Vec<([i64; 2], u32)> = u.arbitrary()?;
+            let tree = Tree2D::from_nodes_with_options(nodes, arbitrary_options(u)?);
+            let locations: Vec<[i64; 2]> = u.arbitrary()?;
+            let max_neighbours = u.int_in_range(0..=10)?;
+            let batch_options = BatchOptions::new().sort(u.arbitrary()?);
+            let actual = tree.find_nearest_batch(
+                &locations,
+                u64::MAX,
+                max_neighbours,
+                &EuclideanDistanceSquared,
+                batch_options,
+            );
+            assert_eq!(locations.len(), actual.len());
+            for (location, actual) in locations.iter().zip(actual.iter()) {
+                let expected = tree.find_nearest_by_metric(
+                    location,
+                    u64::MAX,
+                    max_neighbours,
+                    &EuclideanDistanceSquared,
+                );
+                assert_eq!(expected, actual);
+            }
+            #[cfg(feature = "rayon")]
+            {
+                let par_actual = tree.par_find_nearest_batch(
+                    &locations,
+                    u64::MAX,
+                    max_neighbours,
+                    &EuclideanDistanceSquared,
+                    batch_options,
+                );
+                assert_eq!(actual, par_actual);
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn find_nearest_batch_works_with_unlimited_neighbours() {
+        let nodes = vec![([0, 0], 0_u32), ([1, 1], 1), ([2, 2], 2)];
+        let tree = Tree2D::from_nodes(nodes);
+        let locations = [[0, 0], [2, 2]];
+        let actual = tree.find_nearest_batch(
+            &locations,
+            u64::MAX,
+            usize::MAX,
+            &EuclideanDistanceSquared,
+            BatchOptions::new(),
+        );
+        for results in actual.iter() {
+            assert_eq!(3, results.len());
+        }
+    }
+
+    #[test]
+    fn morton_code_preserves_order_along_axes() {
+        arbtest(|u| {
+            let a: [i64; 2] = u.arbitrary()?;
+            let mut b = a;
+            b[u.choose_index(2)?] = u.arbitrary()?;
+            // Points that differ in one coordinate are ordered by this coordinate.
+            let i = if a[0] != b[0] { 0 } else { 1 };
+            if a[i] < b[i] {
+                assert!(morton_code(&a) <= morton_code(&b));
+            } else {
+                assert!(morton_code(&a) >= morton_code(&b));
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn morton_code_works() {
+        let codes: Vec<u64> = [[0, 0], [0, 1], [1, 0], [1, 1]]
+            .iter()
+            .map(|[x, y]| morton_code(&[*x << 32, *y << 32]) - morton_code(&[0, 0]))
+            .collect();
+        assert_eq!(vec![0, 1, 2, 3], codes);
+        assert_eq!(0, morton_code(&[i64::MIN, i64::MIN]));
+        assert_eq!(u64::MAX, morton_code(&[i64::MAX, i64::MAX]));
+        assert_eq!(
+            u64::MAX,
+            morton_code(&[i64::MAX, i64::MAX, i64::MAX]) | (1 << 63)
+        );
+    }
+}
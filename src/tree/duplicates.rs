@@ -0,0 +1,177 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::TreeKD;
+use super::TreeOptions;
+use crate::Metric;
+
+impl<C: Ord + Copy + Default, V: Default, const K: usize> TreeKD<C, V, K> {
+    /// Create a new tree from the given nodes with near-duplicates collapsed into one node.
+    ///
+    /// The nodes with equal values that are within `radius` from each other (according to
+    /// `metric`) are merged: the nodes within `radius` from the representative are removed,
+    /// and only the representative is stored in the tree. The representatives are chosen in the
+    /// order of the nodes in the tree, i.e. the result doesn't depend on the order of `nodes`.
+    ///
+    /// Returns the tree and the number of removed nodes.
+    pub fn from_nodes_collapsing_duplicates<M: Metric<C, K>>(
+        nodes: Vec<([C; K], V)>,
+        options: TreeOptions,
+        radius: M::Distance,
+        metric: &M,
+    ) -> (Self, usize)
+    where
+        V: Ord,
+    {
+        let tree = Self::from_nodes_with_options(nodes, options);
+        let (removed, num_removed) = tree.find_duplicates(radius, metric);
+        if num_removed == 0 {
+            return (tree, 0);
+        }
+        let nodes = remove_nodes(tree, removed);
+        (Self::from_nodes_with_options(nodes, options), num_removed)
+    }
+
+    /// The same as [`from_nodes_collapsing_duplicates`](Self::from_nodes_collapsing_duplicates)
+    /// but the tree is built in parallel (see [`par_from_nodes`](Self::par_from_nodes)).
+    #[cfg(feature = "rayon")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rayon")))]
+    pub fn par_from_nodes_collapsing_duplicates<M: Metric<C, K>>(
+        nodes: Vec<([C; K], V)>,
+        options: TreeOptions,
+        radius: M::Distance,
+        metric: &M,
+    ) -> (Self, usize)
+    where
+        C: Send,
+        V: Ord + Send,
+    {
+        let tree = Self::par_from_nodes_with_options(nodes, options);
+        let (removed, num_removed) = tree.find_duplicates(radius, metric);
+        if num_removed == 0 {
+            return (tree, 0);
+        }
+        let nodes = remove_nodes(tree, removed);
+        (
+            Self::par_from_nodes_with_options(nodes, options),
+            num_removed,
+        )
+    }
+
+    /// Marks the nodes that have a representative with the same value within `radius`.
+    ///
+    /// Returns the marks and the number of marked nodes.
+    fn find_duplicates<M: Metric<C, K>>(
+        &self,
+        radius: M::Distance,
+        metric: &M,
+    ) -> (Vec<bool>, usize)
+    where
+        V: Eq,
+    {
+        let mut removed = vec![false; self.nodes.len()];
+        let mut num_removed = 0;
+        for (i, node) in self.nodes.iter().enumerate() {
+            if removed[i] {
+                continue;
+            }
+            for (_, j) in self.find_indices_within_radius(&node.location, radius, metric) {
+                if j != i && !removed[j] && self.nodes[j].value == node.value {
+                    removed[j] = true;
+                    num_removed += 1;
+                }
+            }
+        }
+        (removed, num_removed)
+    }
+}
+
+fn remove_nodes<C: Ord + Copy + Default, V: Default, const K: usize>(
+    tree: TreeKD<C, V, K>,
+    removed: Vec<bool>,
+) -> Vec<([C; K], V)> {
+    tree.into_nodes()
+        .zip(removed)
+        .filter_map(|(node, removed)| (!removed).then_some(node))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OrthogonalDistance;
+    use crate::Tree2D;
+    use crate::tree::testing::arbitrary_options;
+    use crate::tree::testing::shuffle;
+    use arbtest::arbtest;
+
+    #[test]
+    fn from_nodes_collapsing_duplicates_works() {
+        arbtest(|u| {
+            // Few distinct coordinates and values to produce many duplicates.
+            let mut nodes = Vec::new();
+            for _ in 0..u.int_in_range(0..=100)? {
+                let location = [u.int_in_range(-10..=10)?, u.int_in_range(-10..=10)?];
+                nodes.push((location, u.int_in_range(0..=2)?));
+            }
+            let options = arbitrary_options(u)?;
+            let radius = u.int_in_range(0..=5)?;
+            let (tree, num_removed) = Tree2D::from_nodes_collapsing_duplicates(
+                nodes.clone(),
+                options,
+                radius,
+                &OrthogonalDistance,
+            );
+            assert_eq!(nodes.len(), tree.len() + num_removed);
+            let is_duplicate = |(a, x): (&[i64; 2], &u32), (b, y): (&[i64; 2], &u32)| {
+                x == y && Metric::<i64>::distance(&OrthogonalDistance, a, b) <= radius
+            };
+            // The remaining nodes are not duplicates of each other.
+            for (i, a) in tree.iter().enumerate() {
+                for b in tree.iter().skip(i + 1) {
+                    assert!(!is_duplicate(a, b), "a = {a:?}, b = {b:?}");
+                }
+            }
+            // Each removed node has a representative.
+            let mut remaining: Vec<_> = tree.iter().map(|(x, v)| (*x, *v)).collect();
+            for (location, value) in nodes.iter() {
+                let node = (location, value);
+                if let Some(i) = remaining.iter().position(|(x, v)| (x, v) == node) {
+                    remaining.swap_remove(i);
+                    continue;
+                }
+                assert!(tree.iter().any(|other| is_duplicate(node, other)));
+            }
+            // The result doesn't depend on the order of the nodes.
+            let mut shuffled = nodes.clone();
+            shuffle(u, &mut shuffled)?;
+            let (actual, _) = Tree2D::from_nodes_collapsing_duplicates(
+                shuffled,
+                options,
+                radius,
+                &OrthogonalDistance,
+            );
+            assert_eq!(tree, actual);
+            Ok(())
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn from_nodes_collapsing_duplicates_on_earth() {
+        use crate::EarthDistance;
+        use alloc::string::String;
+        // Roughly 1 metre in nanodegrees of latitude.
+        const METRE: i64 = 9_000;
+        let nodes = vec![
+            ([0, 0], String::from("entrance")),
+            ([0, 3 * METRE], String::from("entrance")),
+            ([0, 6 * METRE], String::from("shop")),
+            ([0, 100 * METRE], String::from("entrance")),
+        ];
+        let (tree, num_removed) =
+            Tree2D::from_nodes_collapsing_duplicates(nodes, TreeOptions::new(), 10, &EarthDistance);
+        assert_eq!(1, num_removed);
+        assert_eq!(3, tree.len());
+    }
+}
@@ -0,0 +1,279 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::TreeKD;
+use crate::Metric;
+
+/// Static tree with the number of removed nodes.
+#[derive(Debug)]
+struct Level<C, V, const K: usize> {
+    /// Removed nodes have `None` value.
+    tree: TreeKD<C, Option<V>, K>,
+    num_removed: usize,
+}
+
+impl<C: Ord + Copy + Default, V: Ord, const K: usize> Level<C, V, K> {
+    fn new(nodes: Vec<([C; K], Option<V>)>) -> Self {
+        Self {
+            tree: TreeKD::from_nodes(nodes),
+            num_removed: 0,
+        }
+    }
+
+    /// Moves the nodes that were not removed out of the tree.
+    fn into_nodes(self) -> impl Iterator<Item = ([C; K], Option<V>)> {
+        self.tree.into_nodes().filter(|(_, value)| value.is_some())
+    }
+}
+
+/// Two-dimensional tree that supports insertion and removal of nodes.
+pub type DynamicTree2D<C, V> = DynamicTreeKD<C, V, 2>;
+
+/// `K`-dimensional tree that supports insertion and removal of nodes.
+///
+/// The nodes are stored in a forest of static [`TreeKD`]s where the tree at level _i_ has at most
+/// _2<sup>i</sup>_ nodes.
+/// A new node is merged with the trees from the consecutive non-empty levels, and the resulting
+/// tree is placed at the first empty level, i.e. each node is moved _O(log n)_ times on average.
+/// Removed nodes are marked as such, and the tree is rebuilt when half of its nodes are removed.
+/// All queries search each of the _O(log n)_ trees.
+///
+/// # References
+///
+/// - <https://en.wikipedia.org/wiki/K-d_tree>
+/// - Bentley, J. L., & Saxe, J. B. (1980). Decomposable searching problems I.
+///   Static-to-dynamic transformation. Journal of Algorithms, 1(4), 301–358.
+#[derive(Debug)]
+pub struct DynamicTreeKD<C, V, const K: usize> {
+    levels: Vec<Option<Level<C, V, K>>>,
+    len: usize,
+}
+
+impl<C: Ord + Copy + Default, V: Ord, const K: usize> DynamicTreeKD<C, V, K> {
+    /// Create an empty tree.
+    pub fn new() -> Self {
+        Self {
+            levels: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Create a new tree from the given nodes.
+    ///
+    /// All nodes are placed in a single static tree.
+    pub fn from_nodes(nodes: Vec<([C; K], V)>) -> Self {
+        let mut tree = Self::new();
+        if nodes.is_empty() {
+            return tree;
+        }
+        let level = (usize::BITS - (nodes.len() - 1).leading_zeros()) as usize;
+        tree.levels.resize_with(level + 1, || None);
+        tree.len = nodes.len();
+        tree.levels[level] = Some(Level::new(
+            nodes
+                .into_iter()
+                .map(|(location, value)| (location, Some(value)))
+                .collect(),
+        ));
+        tree
+    }
+
+    /// Inserts a new node.
+    pub fn insert(&mut self, location: [C; K], value: V) {
+        self.len += 1;
+        let mut nodes = vec![(location, Some(value))];
+        for (i, slot) in self.levels.iter_mut().enumerate() {
+            if let Some(level) = slot.take() {
+                nodes.extend(level.into_nodes());
+            }
+            if nodes.len() <= 1 << i {
+                *slot = Some(Level::new(nodes));
+                return;
+            }
+        }
+        self.levels.push(Some(Level::new(nodes)));
+    }
+
+    /// Removes the node with the specified location and value.
+    ///
+    /// Returns the removed value or `None` if there is no such node.
+    pub fn remove(&mut self, location: &[C; K], value: &V) -> Option<V> {
+        for slot in self.levels.iter_mut() {
+            let Some(level) = slot else {
+                continue;
+            };
+            let Some(i) = level
+                .tree
+                .find_indices_in_rect(*location, *location)
+                .find(|i| level.tree.nodes[*i].value.as_ref() == Some(value))
+            else {
+                continue;
+            };
+            let removed = level.tree.nodes[i].value.take();
+            level.num_removed += 1;
+            self.len -= 1;
+            if 2 * level.num_removed >= level.tree.len() {
+                let nodes: Vec<_> = slot
+                    .take()
+                    .into_iter()
+                    .flat_map(Level::into_nodes)
+                    .collect();
+                if !nodes.is_empty() {
+                    *slot = Some(Level::new(nodes));
+                }
+            }
+            return removed;
+        }
+        None
+    }
+
+    /// Returns up to `max_neighbours` nodes within `max_distance` that are closest to the `location`.
+    ///
+    /// See [`TreeKD::find_nearest_by_metric`].
+    pub fn find_nearest_by_metric<M: Metric<C, K>>(
+        &self,
+        location: &[C; K],
+        mut max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+    ) -> Vec<(M::Distance, &[C; K], &V)> {
+        let mut neighbours = Vec::new();
+        if max_neighbours == 0 {
+            return neighbours;
+        }
+        for level in self.levels.iter().flatten() {
+            let level_neighbours = level.tree.find_nearest_where(
+                location,
+                max_distance,
+                max_neighbours,
+                metric,
+                |_, value| value.is_some(),
+            );
+            neighbours.extend(
+                level_neighbours
+                    .into_iter()
+                    .filter_map(|(d, location, value)| Some((d, location, value.as_ref()?))),
+            );
+            neighbours.sort_by_key(|(d, ..)| *d);
+            neighbours.truncate(max_neighbours);
+            if neighbours.len() == max_neighbours {
+                // The trees at the next levels can only improve on the farthest neighbour.
+                max_distance = neighbours[max_neighbours - 1].0;
+            }
+        }
+        neighbours
+    }
+
+    /// Returns an iterator over nodes that lie inside the rectangle with corners `min` and `max`.
+    ///
+    /// See [`TreeKD::find_in_rect`].
+    pub fn find_in_rect(&self, min: [C; K], max: [C; K]) -> impl Iterator<Item = (&[C; K], &V)> {
+        self.levels.iter().flatten().flat_map(move |level| {
+            level
+                .tree
+                .find_in_rect(min, max)
+                .filter_map(|(location, value)| Some((location, value.as_ref()?)))
+        })
+    }
+
+    /// Returns an iterator over nodes.
+    pub fn iter(&self) -> impl Iterator<Item = (&[C; K], &V)> {
+        self.levels.iter().flatten().flat_map(|level| {
+            level
+                .tree
+                .iter()
+                .filter_map(|(location, value)| Some((location, value.as_ref()?)))
+        })
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the tree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<C: Ord + Copy + Default, V: Ord, const K: usize> Default for DynamicTreeKD<C, V, K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanDistanceSquared;
+    use arbitrary::Unstructured;
+    use arbtest::arbtest;
+
+    #[test]
+    fn insert_remove_find_nearest() {
+        arbtest(|u| {
+            let initial_nodes: Vec<([i64; 2], u32)> = (0..u.int_in_range(0..=50)?)
+                .map(|_| arbitrary_node(u))
+                .collect::<Result<_, _>>()?;
+            let mut tree = DynamicTree2D::from_nodes(initial_nodes.clone());
+            let mut expected = initial_nodes;
+            let num_operations = u.int_in_range(0..=200)?;
+            for _ in 0..num_operations {
+                match u.int_in_range(0..=2)? {
+                    0 | 1 => {
+                        let (location, value) = arbitrary_node(u)?;
+                        tree.insert(location, value);
+                        expected.push((location, value));
+                    }
+                    _ if !expected.is_empty() => {
+                        let i = u.choose_index(expected.len())?;
+                        let (location, value) = expected.swap_remove(i);
+                        assert_eq!(Some(value), tree.remove(&location, &value));
+                    }
+                    _ => {
+                        let (location, value) = arbitrary_node(u)?;
+                        assert_eq!(None, tree.remove(&location, &value));
+                    }
+                }
+                assert_eq!(expected.len(), tree.len());
+                for (i, level) in tree.levels.iter().enumerate() {
+                    if let Some(level) = level {
+                        assert!(level.tree.len() <= 1 << i);
+                        assert!(2 * level.num_removed < level.tree.len());
+                    }
+                }
+                let location = [u.int_in_range(-120..=120)?, u.int_in_range(-120..=120)?];
+                let max_neighbours = u.int_in_range(0..=10)?;
+                let actual: Vec<_> = tree
+                    .find_nearest_by_metric(
+                        &location,
+                        u64::MAX,
+                        max_neighbours,
+                        &EuclideanDistanceSquared,
+                    )
+                    .into_iter()
+                    .map(|(d, ..)| d)
+                    .collect();
+                let mut distances: Vec<_> = expected
+                    .iter()
+                    .map(|(other, _)| EuclideanDistanceSquared.distance(other, &location))
+                    .collect();
+                distances.sort_unstable();
+                distances.truncate(max_neighbours);
+                assert_eq!(distances, actual);
+            }
+            let mut actual: Vec<_> = tree.iter().map(|(l, v)| (*l, *v)).collect();
+            actual.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(expected, actual);
+            Ok(())
+        });
+    }
+
+    fn arbitrary_node(u: &mut Unstructured<'_>) -> arbitrary::Result<([i64; 2], u32)> {
+        let location = [u.int_in_range(-100..=100)?, u.int_in_range(-100..=100)?];
+        let value = u.int_in_range(0..=10)?;
+        Ok((location, value))
+    }
+}
@@ -0,0 +1,425 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::EMPTY;
+use super::Layout;
+use super::Links;
+use super::Node;
+use super::Nodes;
+use super::Scratch;
+use super::SearchStats;
+use super::Shape;
+use super::Subtree;
+use super::TreeKD;
+use super::TreeOptions;
+use super::io::layout_from_u8;
+use super::io::layout_to_u8;
+use super::num_splits;
+use crate::Metric;
+
+/// The first bytes of the file in flat RGC format.
+const MAGIC: [u8; 8] = *b"RGCFLAT\0";
+
+impl<const K: usize> TreeKD<i64, String, K> {
+    /// Writes the tree into a stream in flat RGC format.
+    ///
+    /// Unlike [`write`](Self::write) the data is not compressed, and every column starts at
+    /// a multiple of 8 bytes. The resulting file can be queried without decoding via
+    /// [`TreeKDRef`].
+    ///
+    /// # Flat RGC format
+    ///
+    /// All integers are little-endian.
+    ///
+    /// - Header: magic `RGCFLAT\0`, the number of nodes, the leaf size, and the layout as `u64`.
+    /// - Locations: `K` coordinates of each node as `i64`.
+    /// - Links: the lesser and the greater child index of each split node as `u32`, or as `u64` if
+    ///   the tree has more than `u32::MAX` nodes. The links are stored only in
+    ///   [`Layout::Explicit`] and [`Layout::VanEmdeBoas`].
+    /// - Names: the offsets of the names of each node and the end offset as `u64`,
+    ///   followed by the UTF-8 bytes of the names.
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn write_flat(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&(self.nodes.len() as u64).to_le_bytes())?;
+        writer.write_all(&(self.options.leaf_size as u64).to_le_bytes())?;
+        writer.write_all(&u64::from(layout_to_u8(self.options.layout)).to_le_bytes())?;
+        for Node { location, .. } in self.nodes.iter() {
+            for coord in location.iter() {
+                writer.write_all(&coord.to_le_bytes())?;
+            }
+        }
+        let wide = Links::is_wide(self.nodes.len());
+        for [lesser_index, greater_index] in self.links.iter() {
+            if wide {
+                writer.write_all(&(lesser_index as u64).to_le_bytes())?;
+                writer.write_all(&(greater_index as u64).to_le_bytes())?;
+            } else {
+                writer.write_all(&(lesser_index as u32).to_le_bytes())?;
+                writer.write_all(&(greater_index as u32).to_le_bytes())?;
+            }
+        }
+        let mut offset = 0_u64;
+        writer.write_all(&offset.to_le_bytes())?;
+        for Node { value, .. } in self.nodes.iter() {
+            offset += value.len() as u64;
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        for Node { value, .. } in self.nodes.iter() {
+            writer.write_all(value.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Borrowed view of the tree in flat RGC format.
+///
+/// The nodes are read directly from the bytes (e.g. a memory-mapped file) without copying, and the
+/// names are decoded only for the nodes that are returned by the search.
+/// See [`TreeKD::write_flat`] for the description of the format.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Debug, Clone, Copy)]
+pub struct TreeKDRef<'a, const K: usize> {
+    locations: &'a [u8],
+    links: &'a [u8],
+    /// Whether the links are stored as `u64`.
+    wide: bool,
+    name_offsets: &'a [u8],
+    names: &'a [u8],
+    len: usize,
+    shape: Shape,
+}
+
+/// Borrowed view of [`Tree2D`](crate::Tree2D) in flat RGC format.
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub type Tree2DRef<'a> = TreeKDRef<'a, 2>;
+
+impl<'a, const K: usize> TreeKDRef<'a, K> {
+    /// Create a view of the tree that was written by [`TreeKD::write_flat`].
+    ///
+    /// Only the header and the links are validated; invalid names are reported when they are
+    /// accessed.
+    pub fn new(bytes: &'a [u8]) -> std::io::Result<Self> {
+        if !Self::is_flat(bytes) {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        let mut bytes = Bytes(&bytes[MAGIC.len()..]);
+        let len = usize::try_from(bytes.take_u64()?).map_err(|_| invalid_data())?;
+        let leaf_size = usize::try_from(bytes.take_u64()?).map_err(|_| invalid_data())?;
+        if leaf_size == 0 {
+            return Err(invalid_data());
+        }
+        // A bucket can't be longer than the tree, hence larger leaf sizes give the same shape.
+        let leaf_size = leaf_size.min(len.max(1));
+        let layout = u8::try_from(bytes.take_u64()?).map_err(|_| invalid_data())?;
+        let layout = layout_from_u8(layout)?;
+        let options = TreeOptions::new().leaf_size(leaf_size).layout(layout);
+        let locations = bytes.take_array(len, K * size_of::<i64>())?;
+        // The implicit layout doesn't store the links.
+        let num_splits = match layout {
+            Layout::Explicit | Layout::VanEmdeBoas => num_splits(len, leaf_size),
+            Layout::Implicit => 0,
+        };
+        let wide = Links::is_wide(len);
+        let index_len = if wide {
+            size_of::<u64>()
+        } else {
+            size_of::<u32>()
+        };
+        let links = bytes.take_array(num_splits, 2 * index_len)?;
+        let name_offsets = bytes.take_array(len + 1, size_of::<u64>())?;
+        let names = bytes.0;
+        let tree = Self {
+            locations,
+            links,
+            wide,
+            name_offsets,
+            names,
+            len,
+            shape: Shape::new(len, options),
+        };
+        if (0..num_splits).any(|i| tree.link(i).into_iter().any(|index| index > len)) {
+            return Err(invalid_data());
+        }
+        Ok(tree)
+    }
+
+    /// Returns `true` if the `bytes` start with the magic of flat RGC format.
+    ///
+    /// This distinguishes flat files from the files in other formats; use [`new`](Self::new) to
+    /// validate the rest of the header.
+    pub fn is_flat(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    /// Returns up to `max_neighbours` nodes within `max_distance` that are closest to the `location`.
+    ///
+    /// This is the same as [`TreeKD::find_nearest_by_metric`] but the names are decoded only for
+    /// the returned nodes.
+    /// Returns an error if any of these names is not valid UTF-8.
+    #[allow(clippy::type_complexity)]
+    pub fn find_nearest<M: Metric<i64, K>>(
+        &self,
+        location: &[i64; K],
+        max_distance: M::Distance,
+        max_neighbours: usize,
+        metric: &M,
+    ) -> std::io::Result<Vec<(M::Distance, [i64; K], &'a str)>> {
+        let mut neighbours = Vec::new();
+        self.find_nearest_indices(
+            location,
+            max_distance,
+            max_neighbours,
+            |i| Some(metric.distance(&self.location(i), location)),
+            |axis, split_value| metric.axis_lower_bound(location, axis, split_value),
+            &mut SearchStats::default(),
+            &mut Scratch::new(),
+            |d, i| neighbours.push((d, i)),
+        );
+        neighbours
+            .into_iter()
+            .map(|(d, i)| Ok((d, self.location(i), self.name(i)?)))
+            .collect()
+    }
+
+    /// Returns the location and the name of the node with the specified `index` or `None` if the
+    /// index is out of bounds.
+    ///
+    /// The index is the same as in [`TreeKD::get`].
+    pub fn get(&self, index: usize) -> std::io::Result<Option<([i64; K], &'a str)>> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        Ok(Some((self.location(index), self.name(index)?)))
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the tree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn location(&self, i: usize) -> [i64; K] {
+        let offset = i * K * size_of::<i64>();
+        core::array::from_fn(|j| read_u64(self.locations, offset + j * size_of::<i64>()) as i64)
+    }
+
+    /// Returns the one-based indices of the children of the split node.
+    ///
+    /// Returns no children if the index is out of bounds to not panic on invalid links.
+    fn link(&self, i: usize) -> [usize; 2] {
+        let index_len = if self.wide {
+            size_of::<u64>()
+        } else {
+            size_of::<u32>()
+        };
+        if (i + 1) * 2 * index_len > self.links.len() {
+            return [EMPTY, EMPTY];
+        }
+        if self.wide {
+            let offset = 2 * i * size_of::<u64>();
+            [
+                read_u64(self.links, offset) as usize,
+                read_u64(self.links, offset + size_of::<u64>()) as usize,
+            ]
+        } else {
+            let offset = 2 * i * size_of::<u32>();
+            [
+                read_u32(self.links, offset) as usize,
+                read_u32(self.links, offset + size_of::<u32>()) as usize,
+            ]
+        }
+    }
+
+    fn name(&self, i: usize) -> std::io::Result<&'a str> {
+        let offset = |i: usize| read_u64(self.name_offsets, i * size_of::<u64>());
+        let start = usize::try_from(offset(i)).map_err(|_| invalid_data())?;
+        let end = usize::try_from(offset(i + 1)).map_err(|_| invalid_data())?;
+        let bytes = self.names.get(start..end).ok_or_else(invalid_data)?;
+        core::str::from_utf8(bytes).map_err(|_| invalid_data())
+    }
+}
+
+impl<const K: usize> Nodes<i64, K> for TreeKDRef<'_, K> {
+    fn root(&self) -> Option<Subtree> {
+        (self.len != 0).then_some(Subtree {
+            coord_index: 0,
+            index: 0,
+            len: self.len,
+        })
+    }
+
+    fn children(&self, subtree: Subtree) -> Option<[Option<Subtree>; 2]> {
+        self.shape.children::<K>(subtree, |i| self.link(i))
+    }
+
+    /// The range is clamped to the number of nodes to not panic on invalid links.
+    fn bucket(&self, subtree: Subtree) -> Range<usize> {
+        let range = subtree.range();
+        range.start.min(self.len)..range.end.min(self.len)
+    }
+
+    fn split_value(&self, index: usize, coord_index: usize) -> i64 {
+        self.location(index)[coord_index]
+    }
+}
+
+/// The bytes that are not parsed yet.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> std::io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    /// Takes `count` elements of `len` bytes each.
+    fn take_array(&mut self, count: usize, len: usize) -> std::io::Result<&'a [u8]> {
+        let n = count.checked_mul(len).ok_or_else(invalid_data)?;
+        self.take(n)
+    }
+
+    fn take_u64(&mut self) -> std::io::Result<u64> {
+        Ok(read_u64(self.take(size_of::<u64>())?, 0))
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0_u8; size_of::<u64>()];
+    buf.copy_from_slice(&bytes[offset..offset + size_of::<u64>()]);
+    u64::from_le_bytes(buf)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0_u8; size_of::<u32>()];
+    buf.copy_from_slice(&bytes[offset..offset + size_of::<u32>()]);
+    u32::from_le_bytes(buf)
+}
+
+fn invalid_data() -> std::io::Error {
+    std::io::ErrorKind::InvalidData.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EarthDistance;
+    use crate::EuclideanDistanceSquared;
+    use crate::Tree2D;
+    use crate::tree::testing::arbitrary_tree;
+    use alloc::vec;
+    use arbtest::arbtest;
+
+    #[test]
+    fn tree_ref_matches_tree() {
+        arbtest(|u| {
+            let tree: Tree2D<i64, String> = arbitrary_tree(u, 100)?;
+            let mut buf = Vec::new();
+            tree.write_flat(&mut buf).unwrap();
+            let tree_ref = Tree2DRef::new(&buf).unwrap();
+            assert_eq!(tree.len(), tree_ref.len());
+            for (i, (location, name)) in tree.iter().enumerate() {
+                assert_eq!(Some((*location, name.as_str())), tree_ref.get(i).unwrap());
+            }
+            assert_eq!(None, tree_ref.get(tree.len()).unwrap());
+            let location = [u.int_in_range(-120..=120)?, u.int_in_range(-120..=120)?];
+            let max_neighbours = u.int_in_range(0..=20)?;
+            let max_distance = u.int_in_range(0..=30_000)?;
+            let expected: Vec<_> = tree
+                .find_nearest_by_metric(
+                    &location,
+                    max_distance,
+                    max_neighbours,
+                    &EuclideanDistanceSquared,
+                )
+                .into_iter()
+                .map(|(d, location, name)| (d, *location, name.as_str()))
+                .collect();
+            let actual = tree_ref
+                .find_nearest(
+                    &location,
+                    max_distance,
+                    max_neighbours,
+                    &EuclideanDistanceSquared,
+                )
+                .unwrap();
+            assert_eq!(expected, actual);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn tree_ref_works_with_unlimited_neighbours() {
+        let tree = Tree2D::from_nodes(vec![
+            ([1, 2], String::from("a")),
+            ([3, 4], String::from("b")),
+        ]);
+        let mut flat = Vec::new();
+        tree.write_flat(&mut flat).unwrap();
+        let tree_ref = Tree2DRef::new(&flat).unwrap();
+        let neighbours = tree_ref
+            .find_nearest(&[0, 0], u64::MAX, usize::MAX, &EuclideanDistanceSquared)
+            .unwrap();
+        assert_eq!(2, neighbours.len());
+    }
+
+    #[test]
+    fn is_flat_works() {
+        let tree = Tree2D::from_nodes(vec![([1, 2], String::from("a"))]);
+        let mut flat = Vec::new();
+        tree.write_flat(&mut flat).unwrap();
+        assert!(Tree2DRef::is_flat(&flat));
+        // Truncated flat file is reported as invalid rather than as a file in other format.
+        assert!(Tree2DRef::is_flat(&flat[..MAGIC.len()]));
+        assert!(Tree2DRef::new(&flat[..MAGIC.len()]).is_err());
+        let mut other = Vec::new();
+        tree.write(&mut other).unwrap();
+        assert!(!Tree2DRef::is_flat(&other));
+        assert!(!Tree2DRef::is_flat(&[]));
+    }
+
+    #[test]
+    fn tree_ref_does_not_panic_on_invalid_data() {
+        arbtest(|u| {
+            let tree: Tree2D<i64, String> = arbitrary_tree(u, 100)?;
+            let mut buf = Vec::new();
+            tree.write_flat(&mut buf).unwrap();
+            for _ in 0..u.int_in_range(1..=10)? {
+                if buf.is_empty() {
+                    break;
+                }
+                match u.int_in_range(0..=2)? {
+                    0 => {
+                        let i = u.choose_index(buf.len())?;
+                        buf[i] = u.arbitrary()?;
+                    }
+                    1 => {
+                        // Overwrite the length, the leaf size or the layout.
+                        let offset = MAGIC.len() + u.choose_index(3)? * size_of::<u64>();
+                        let values = [0, 1, 2, u64::MAX, u.arbitrary()?];
+                        let value = *u.choose(&values)?;
+                        if let Some(field) = buf.get_mut(offset..offset + size_of::<u64>()) {
+                            field.copy_from_slice(&value.to_le_bytes());
+                        }
+                    }
+                    _ => buf.truncate(u.choose_index(buf.len())?),
+                }
+            }
+            let Ok(tree_ref) = Tree2DRef::new(&buf) else {
+                return Ok(());
+            };
+            let location = [u.int_in_range(-120..=120)?, u.int_in_range(-120..=120)?];
+            let _ = tree_ref.find_nearest(&location, u64::MAX, 10, &EarthDistance);
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,92 @@
+use core::ops::Range;
+
+/// The shape of the tree in [`Layout::Implicit`](super::Layout::Implicit).
+///
+/// The split nodes form a perfect binary tree that is stored in Eytzinger order, i.e. the
+/// children of the node in slot _i_ occupy slots _2i + 1_ and _2i + 2_.
+/// The slots that are past the split nodes are the leaf buckets. The buckets are stored after
+/// the split nodes in the order of their slots, and their sizes differ by at most one.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(super) struct ImplicitShape {
+    pub(super) num_splits: usize,
+    /// The number of nodes in the shortest bucket.
+    bucket_len: usize,
+    /// The number of buckets that have one more node.
+    num_longer_buckets: usize,
+}
+
+impl ImplicitShape {
+    pub(super) fn new(len: usize, leaf_size: usize) -> Self {
+        // The smallest perfect tree such that the buckets have no more than `leaf_size` nodes,
+        // i.e. `len + 1 <= (num_splits + 1) * (leaf_size + 1)`.
+        let num_buckets = (len + 1)
+            .div_ceil(leaf_size.saturating_add(1))
+            .next_power_of_two();
+        let num_splits = num_buckets - 1;
+        let num_bucket_nodes = len - num_splits;
+        Self {
+            num_splits,
+            bucket_len: num_bucket_nodes / num_buckets,
+            num_longer_buckets: num_bucket_nodes % num_buckets,
+        }
+    }
+
+    /// Returns the range of nodes in the leaf bucket that occupies the `slot`.
+    pub(super) fn bucket(&self, slot: usize) -> Range<usize> {
+        let i = slot - self.num_splits;
+        self.num_splits + self.bucket_offset(i)..self.num_splits + self.bucket_offset(i + 1)
+    }
+
+    /// Returns the number of nodes in the subtree that occupies the `slot`.
+    pub(super) fn subtree_len(&self, slot: usize) -> usize {
+        let num_splits = self.num_splits;
+        let num_slots = 2 * num_splits + 1;
+        let mut len = 0;
+        // The slots of the subtree at the same depth are contiguous.
+        let mut slots = slot..slot + 1;
+        while slots.start < num_slots {
+            len += slots.end.min(num_splits).saturating_sub(slots.start);
+            let first_bucket_slot = slots.start.max(num_splits);
+            let last_bucket_slot = slots.end.min(num_slots);
+            if first_bucket_slot < last_bucket_slot {
+                len += self.bucket_offset(last_bucket_slot - num_splits)
+                    - self.bucket_offset(first_bucket_slot - num_splits);
+            }
+            slots = 2 * slots.start + 1..2 * slots.end + 1;
+        }
+        len
+    }
+
+    /// Returns the number of nodes in the first `i` buckets.
+    fn bucket_offset(&self, i: usize) -> usize {
+        i * self.bucket_len + i.min(self.num_longer_buckets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arbtest::arbtest;
+
+    #[test]
+    fn implicit_shape_works() {
+        arbtest(|u| {
+            let len = u.int_in_range(0..=10_000)?;
+            let leaf_size = u.int_in_range(1..=100)?;
+            let shape = ImplicitShape::new(len, leaf_size);
+            assert_eq!(len, shape.subtree_len(0));
+            for slot in 0..shape.num_splits {
+                assert_eq!(
+                    shape.subtree_len(slot),
+                    1 + shape.subtree_len(2 * slot + 1) + shape.subtree_len(2 * slot + 2)
+                );
+            }
+            for slot in shape.num_splits..2 * shape.num_splits + 1 {
+                let bucket = shape.bucket(slot);
+                assert_eq!(bucket.len(), shape.subtree_len(slot));
+                assert!(bucket.len() <= leaf_size);
+            }
+            Ok(())
+        });
+    }
+}
@@ -1,31 +1,70 @@
+use super::Layout;
+use super::Links;
 use super::Node;
-use super::Tree2D;
+use super::Nodes;
+use super::Shape;
+use super::TreeKD;
+use super::TreeOptions;
+use super::num_splits;
 
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 
-impl Tree2D<i64, String> {
+/// The first bytes of the stream in RGC format.
+const MAGIC: [u8; 4] = *b"RGC\0";
+
+/// The version of RGC format that is written by [`TreeKD::write`].
+const VERSION: u32 = 1;
+
+impl<const K: usize> TreeKD<i64, String, K> {
     /// Writes the tree into a stream in RGC format.
     ///
     /// RGC is an internal format of this crate that uses columnar storage to compress the data.
+    /// Each of the `K` coordinates is stored in a separate column.
+    /// The header contains the magic `RGC\0`, the format version, the number of nodes, the leaf size,
+    /// and the layout. The child indices are stored only
+    /// for the split nodes in [`Layout::Explicit`] and [`Layout::VanEmdeBoas`].
+    /// The child indices are stored as 64-bit integers only if the tree has more than `u32::MAX`
+    /// nodes.
     #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
     pub fn write(&self, mut writer: impl std::io::Write) -> std::io::Result<()> {
         use super::write::Write;
-        writer.write_u32(self.nodes.len() as u32)?;
-        writer.write_sign_magnitude(self.nodes.iter().map(|Node { location, .. }| location[0]))?;
-        writer.write_sign_magnitude(self.nodes.iter().map(|Node { location, .. }| location[1]))?;
-        writer.write_magnitude_monotonic(
-            self.nodes
-                .iter()
-                .map(|Node { lesser_index, .. }| *lesser_index),
-        )?;
-        writer.write_magnitude_monotonic(
-            self.nodes
-                .iter()
-                .map(|Node { greater_index, .. }| *greater_index),
-        )?;
+        let leaf_size = u32::try_from(self.options.leaf_size)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+        writer.write_bytes(&MAGIC)?;
+        writer.write_u32(VERSION)?;
+        writer.write_u64(self.nodes.len() as u64)?;
+        writer.write_u32(leaf_size)?;
+        writer.write_bytes(&[layout_to_u8(self.options.layout)])?;
+        for i in 0..K {
+            writer
+                .write_sign_magnitude(self.nodes.iter().map(|Node { location, .. }| location[i]))?;
+        }
+        if Links::is_wide(self.nodes.len()) {
+            writer.write_magnitude_monotonic_u64(
+                self.links
+                    .iter()
+                    .map(|[lesser_index, _]| lesser_index as u64),
+            )?;
+            writer.write_magnitude_monotonic_u64(
+                self.links
+                    .iter()
+                    .map(|[_, greater_index]| greater_index as u64),
+            )?;
+        } else {
+            writer.write_magnitude_monotonic(
+                self.links
+                    .iter()
+                    .map(|[lesser_index, _]| lesser_index as u32),
+            )?;
+            writer.write_magnitude_monotonic(
+                self.links
+                    .iter()
+                    .map(|[_, greater_index]| greater_index as u32),
+            )?;
+        }
         // Value is the number of occurences of a particular word.
         let mut words: BTreeMap<&str, usize> = BTreeMap::new();
         let mut word_counts = Vec::with_capacity(self.nodes.len());
@@ -57,8 +96,8 @@ impl Tree2D<i64, String> {
                     .map(|word| words.get(word).copied().expect("Must exist") as u32)
             })
             .collect();
-        writer.write_u32(indices.len() as u32)?;
-        writer.write_magnitude(indices.into_iter())?;
+        writer.write_u64(indices.len() as u64)?;
+        writer.write_magnitude(indices)?;
         Ok(())
     }
 
@@ -77,67 +116,200 @@ impl Tree2D<i64, String> {
     /// ```bash
     /// cargo run --bin geo-coding-cli --release -- convert europe-latest.osm.pbf
     /// ```
+    ///
+    /// Returns [`ErrorKind::InvalidData`](std::io::ErrorKind::InvalidData) if the stream is not
+    /// in RGC format or was written by an unsupported version of this crate.
     #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
     pub fn read(mut reader: impl std::io::Read) -> std::io::Result<Self> {
         use super::read::Read;
-        let num_points = reader.read_u32()? as usize;
-        let mut nodes = vec![Node::default(); num_points];
-        let longitudes = reader.read_sign_magnitude(num_points)?;
-        for (node, longitude) in nodes.iter_mut().zip(longitudes.into_iter()) {
-            node.location[0] = longitude;
+        let mut magic = [0_u8; MAGIC.len()];
+        reader.read_bytes(&mut magic[..])?;
+        if magic != MAGIC || reader.read_u32()? != VERSION {
+            return Err(std::io::ErrorKind::InvalidData.into());
         }
-        let latitudes = reader.read_sign_magnitude(num_points)?;
-        for (node, latitude) in nodes.iter_mut().zip(latitudes.into_iter()) {
-            node.location[1] = latitude;
+        let num_points = usize::try_from(reader.read_u64()?)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
+        let leaf_size = reader.read_u32()? as usize;
+        if leaf_size == 0 {
+            return Err(std::io::ErrorKind::InvalidData.into());
         }
-        let lesser_indices = reader.read_magnitude_monotonic(num_points)?;
-        for (node, lesser_index) in nodes.iter_mut().zip(lesser_indices.into_iter()) {
-            node.lesser_index = lesser_index;
-        }
-        let greater_indices = reader.read_magnitude_monotonic(num_points)?;
-        for (node, greater_index) in nodes.iter_mut().zip(greater_indices.into_iter()) {
-            node.greater_index = greater_index;
+        let mut layout = [0_u8];
+        reader.read_bytes(&mut layout[..])?;
+        let layout = layout_from_u8(layout[0])?;
+        let options = TreeOptions::new().leaf_size(leaf_size).layout(layout);
+        // The nodes are allocated only after the first column is read, i.e. the number of nodes
+        // is confirmed by the data.
+        let mut nodes = Vec::new();
+        for i in 0..K {
+            let coordinates = reader.read_sign_magnitude(num_points)?;
+            if i == 0 {
+                nodes.resize(num_points, Node::default());
+            }
+            for (node, coordinate) in nodes.iter_mut().zip(coordinates) {
+                node.location[i] = coordinate;
+            }
         }
+        // The implicit layout doesn't store the links.
+        let num_splits = match layout {
+            Layout::Explicit | Layout::VanEmdeBoas => num_splits(num_points, leaf_size),
+            Layout::Implicit => 0,
+        };
+        let (lesser_indices, greater_indices) = if Links::is_wide(num_points) {
+            (
+                reader.read_magnitude_monotonic_u64(num_splits)?,
+                reader.read_magnitude_monotonic_u64(num_splits)?,
+            )
+        } else {
+            let lesser_indices = reader.read_magnitude_monotonic(num_splits)?;
+            let greater_indices = reader.read_magnitude_monotonic(num_splits)?;
+            (
+                lesser_indices.into_iter().map(u64::from).collect(),
+                greater_indices.into_iter().map(u64::from).collect(),
+            )
+        };
+        let links = Links::from_columns(num_points, lesser_indices, greater_indices)
+            .ok_or(std::io::ErrorKind::InvalidData)?;
         let word_counts = reader.read_magnitude(num_points)?;
+        let total_word_count: u64 = word_counts.iter().copied().map(u64::from).sum();
         let num_words = reader.read_u32()? as usize;
+        // Each word in the dictionary is used at least once.
+        if num_words as u64 > total_word_count {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
         let word_lens = reader.read_magnitude(num_words)?;
         let mut words = Vec::with_capacity(num_words);
         let mut buf = Vec::new();
         for word_len in word_lens.iter().copied() {
-            buf.resize(word_len as usize, 0_u8);
-            reader.read_bytes(&mut buf[..])?;
+            // The word is read incrementally to not allocate the memory for the bytes that don't
+            // exist.
+            let word_len = u64::from(word_len);
+            std::io::Read::read_to_end(&mut std::io::Read::take(&mut reader, word_len), &mut buf)?;
+            if buf.len() as u64 != word_len {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
             let word = String::from_utf8(std::mem::take(&mut buf))
                 .map_err(|_| std::io::Error::other("Non-UTF-8 word"))?;
             words.push(word);
         }
         drop(word_lens);
         drop(buf);
-        let num_indices = reader.read_u32()? as usize;
+        let num_indices = reader.read_u64()?;
+        if num_indices != total_word_count {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        let num_indices = usize::try_from(num_indices)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
         let mut indices = reader.read_magnitude(num_indices)?.into_iter();
         let mut buf = String::new();
-        for (node, word_count) in nodes.iter_mut().zip(word_counts.into_iter()) {
+        for (node, word_count) in nodes.iter_mut().zip(word_counts) {
             buf.clear();
             for _ in 0..word_count {
                 let index = indices.next().ok_or(std::io::ErrorKind::InvalidData)?;
-                let word = words[index as usize].as_str();
+                let word = words
+                    .get(index as usize)
+                    .ok_or(std::io::ErrorKind::InvalidData)?;
                 buf.push_str(word);
                 buf.push(' ');
             }
             buf.pop();
             node.value = buf.clone();
         }
-        // TODO validate
-        Ok(Self { nodes })
+        let tree = Self {
+            nodes,
+            links,
+            shape: Shape::new(num_points, options),
+            options,
+        };
+        if !tree.has_valid_links() {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        Ok(tree)
+    }
+
+    /// Returns `true` if every node is reachable from the root exactly once.
+    fn has_valid_links(&self) -> bool {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut visit = |i: usize| match visited.get_mut(i) {
+            Some(visited @ false) => {
+                *visited = true;
+                true
+            }
+            _ => false,
+        };
+        let mut stack = Vec::from_iter(self.root());
+        while let Some(subtree) = stack.pop() {
+            // The split nodes of the explicit layout are the nodes that have links.
+            if matches!(self.shape, Shape::Explicit { leaf_size } if subtree.len > leaf_size)
+                && subtree.index >= self.links.len()
+            {
+                return false;
+            }
+            match self.children(subtree) {
+                Some(children) => {
+                    if !visit(subtree.index) {
+                        return false;
+                    }
+                    stack.extend(children.into_iter().flatten());
+                }
+                None => {
+                    if !subtree.range().all(&mut visit) {
+                        return false;
+                    }
+                }
+            }
+        }
+        visited.into_iter().all(|visited| visited)
+    }
+}
+
+pub(super) fn layout_to_u8(layout: Layout) -> u8 {
+    match layout {
+        Layout::Explicit => 0,
+        Layout::Implicit => 1,
+        Layout::VanEmdeBoas => 2,
+    }
+}
+
+pub(super) fn layout_from_u8(value: u8) -> std::io::Result<Layout> {
+    match value {
+        0 => Ok(Layout::Explicit),
+        1 => Ok(Layout::Implicit),
+        2 => Ok(Layout::VanEmdeBoas),
+        _ => Err(std::io::ErrorKind::InvalidData.into()),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Tree2D;
+    use crate::tree::testing::arbitrary_options;
+    use crate::tree::testing::shuffle;
+    use alloc::vec;
     use arbitrary::Arbitrary;
     use arbitrary::Unstructured;
     use arbtest::arbtest;
 
+    #[test]
+    fn io_works_for_any_dimension() {
+        fn check<const K: usize>(u: &mut Unstructured<'_>) -> arbitrary::Result<()> {
+            let nodes: Vec<([i64; K], String)> = u.arbitrary()?;
+            let mut buf = Vec::new();
+            let tree = TreeKD::from_nodes_with_options(nodes, arbitrary_options(u)?);
+            tree.write(&mut buf).unwrap();
+            let actual = TreeKD::<i64, String, K>::read(&buf[..])
+                .unwrap_or_else(|e| panic!("Decoding failed: {e}; tree = {tree:?}"));
+            assert_eq!(tree, actual);
+            Ok(())
+        }
+        arbtest(|u| {
+            check::<1>(u)?;
+            check::<2>(u)?;
+            check::<3>(u)?;
+            Ok(())
+        });
+    }
+
     #[test]
     fn io_works() {
         arbtest(|u| {
@@ -156,6 +328,145 @@ mod tests {
         });
     }
 
+    #[test]
+    fn write_does_not_depend_on_node_order() {
+        arbtest(|u| {
+            // Few distinct coordinates and names to produce many ties.
+            let mut nodes = Vec::new();
+            for _ in 0..u.int_in_range(0..=100)? {
+                let location = [u.int_in_range(-3..=3)?, u.int_in_range(-3..=3)?];
+                let name = String::from(["a", "b", "c"][u.choose_index(3)?]);
+                nodes.push((location, name));
+            }
+            let mut shuffled = nodes.clone();
+            shuffle(u, &mut shuffled)?;
+            let mut expected = Vec::new();
+            Tree2D::from_nodes(nodes).write(&mut expected).unwrap();
+            let mut actual = Vec::new();
+            Tree2D::from_nodes(shuffled).write(&mut actual).unwrap();
+            assert_eq!(expected, actual);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn read_rejects_invalid_header() {
+        let tree = Tree2D::from_nodes(vec![([1, 2], String::from("a b")), ([3, 4], String::new())]);
+        let mut buf = Vec::new();
+        tree.write(&mut buf).unwrap();
+        let read = |buf: &[u8]| Tree2D::<i64, String>::read(buf).map_err(|e| e.kind());
+        // The header before the format version was introduced: the number of nodes as `u32`
+        // followed by the columns.
+        let mut legacy = Vec::new();
+        legacy.extend(u32::MAX.to_le_bytes());
+        legacy.extend(&buf[12..]);
+        assert_eq!(Err(std::io::ErrorKind::InvalidData), read(&legacy));
+        // Unsupported version.
+        let mut invalid = buf.clone();
+        invalid[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(Err(std::io::ErrorKind::InvalidData), read(&invalid));
+        // The number of nodes that exceeds the size of the data.
+        let mut invalid = buf.clone();
+        invalid[8..16].copy_from_slice(&(u64::MAX >> 1).to_le_bytes());
+        assert!(read(&invalid).is_err());
+        // Truncated data.
+        for len in 0..buf.len() {
+            assert!(read(&buf[..len]).is_err(), "len = {len}");
+        }
+        assert_eq!(tree, Tree2D::<i64, String>::read(&buf[..]).unwrap());
+    }
+
+    #[test]
+    fn read_rejects_invalid_links() {
+        for layout in [Layout::Explicit, Layout::VanEmdeBoas] {
+            let options = TreeOptions::new().leaf_size(2).layout(layout);
+            let nodes: Vec<_> = (0..20).map(|i| ([i, -i], String::from("a"))).collect();
+            let tree = Tree2D::from_nodes_with_options(nodes, options);
+            let (lesser_indices, greater_indices): (Vec<u64>, Vec<u64>) = tree
+                .links
+                .iter()
+                .map(|[lesser, greater]| (lesser as u64, greater as u64))
+                .unzip();
+            // Any other link makes some nodes unreachable.
+            for i in 0..tree.links.len() {
+                for side in 0..2 {
+                    for index in 0..=tree.len() as u64 {
+                        let mut columns = [lesser_indices.clone(), greater_indices.clone()];
+                        if columns[side][i] == index {
+                            continue;
+                        }
+                        columns[side][i] = index;
+                        let [lesser, greater] = columns;
+                        let invalid = Tree2D {
+                            nodes: tree.nodes.clone(),
+                            links: Links::from_columns(tree.len(), lesser, greater).unwrap(),
+                            shape: tree.shape,
+                            options: tree.options,
+                        };
+                        let mut buf = Vec::new();
+                        invalid.write(&mut buf).unwrap();
+                        assert_eq!(
+                            std::io::ErrorKind::InvalidData,
+                            Tree2D::<i64, String>::read(&buf[..]).unwrap_err().kind(),
+                            "link = {i}, side = {side}, index = {index}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn write_rejects_large_leaf_size() {
+        let options = TreeOptions::new().leaf_size(u32::MAX as usize + 1);
+        let tree = Tree2D::from_nodes_with_options(vec![([0, 0], String::new())], options);
+        let error = tree.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidInput, error.kind());
+    }
+
+    #[test]
+    fn magnitude_io_works() {
+        use crate::tree::read::Read;
+        use crate::tree::write::Write;
+        fn check(values: Vec<u64>) {
+            let mut buf = Vec::new();
+            buf.write_magnitude_monotonic_u64(values.iter().copied())
+                .unwrap();
+            let actual = (&buf[..])
+                .read_magnitude_monotonic_u64(values.len())
+                .unwrap();
+            assert_eq!(values, actual);
+            let Ok(values) = values
+                .into_iter()
+                .map(u32::try_from)
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                return;
+            };
+            let mut buf = Vec::new();
+            buf.write_magnitude_monotonic(values.iter().copied())
+                .unwrap();
+            let actual = (&buf[..]).read_magnitude_monotonic(values.len()).unwrap();
+            assert_eq!(values, actual);
+            let mut buf = Vec::new();
+            buf.write_magnitude(values.iter().copied()).unwrap();
+            let actual = (&buf[..]).read_magnitude(values.len()).unwrap();
+            assert_eq!(values, actual);
+        }
+        // Zeros are stored without any bytes.
+        check(vec![0]);
+        check(vec![0; 3]);
+        arbtest(|u| {
+            let mut values: Vec<u64> = u.arbitrary()?;
+            if u.arbitrary()? {
+                values.push(u64::from(u32::MAX) + 1);
+            }
+            check(values);
+            Ok(())
+        });
+    }
+
     struct TestNode([i64; 2], String);
 
     impl<'a> Arbitrary<'a> for TestNode {
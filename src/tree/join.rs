@@ -0,0 +1,279 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::Nodes;
+use super::Subtree;
+use super::TreeKD;
+use crate::Metric;
+
+/// A part of the tree and its bounding box.
+#[derive(Clone, Copy)]
+struct Region<C, const K: usize> {
+    part: Part,
+    /// The number of nodes in the region.
+    ///
+    /// The number is approximate for the split nodes in [`Layout::Implicit`](crate::Layout::Implicit).
+    len: usize,
+    min: [C; K],
+    max: [C; K],
+}
+
+#[derive(Clone, Copy)]
+enum Part {
+    /// Node index.
+    Node(usize),
+    Subtree(Subtree),
+}
+
+impl<C: Ord + Copy + Default, V: Default, const K: usize> TreeKD<C, V, K> {
+    /// Returns an iterator over all pairs of nodes from `self` and `other` that are within
+    /// `radius` from each other.
+    ///
+    /// Each pair is returned as the index of the node in `self`, the index of the node in `other`,
+    /// and the distance computed by `metric`. The indices are the positions of the nodes in
+    /// [`iter`](Self::iter) (see also [`get`](Self::get)). The pairs are returned in no particular
+    /// order.
+    ///
+    /// Both trees are traversed simultaneously: the larger of the two current subtrees is split, and
+    /// the pairs of subtrees are skipped when the lower bound of the distance between their bounding
+    /// boxes (see [`Metric::box_lower_bound`]) exceeds `radius`.
+    pub fn join_within_radius<'a, W, M: Metric<C, K>>(
+        &'a self,
+        other: &'a TreeKD<C, W, K>,
+        radius: M::Distance,
+        metric: &'a M,
+    ) -> impl Iterator<Item = (usize, usize, M::Distance)> + 'a {
+        let mut stack = Vec::new();
+        if let (Some(a), Some(b)) = (self.root_region(), other.root_region()) {
+            stack.push((a, b));
+        }
+        // The pairs of nodes that are checked before visiting the next pair of regions.
+        let mut pending_a = 0..0;
+        let mut pending_b = 0..0;
+        let mut range_b = 0..0;
+        let mut a = 0;
+        core::iter::from_fn(move || {
+            loop {
+                loop {
+                    if let Some(b) = pending_b.next() {
+                        let d = metric.distance(&self.nodes[a].location, &other.nodes[b].location);
+                        if d <= radius {
+                            return Some((a, b, d));
+                        }
+                        continue;
+                    }
+                    let Some(next_a) = pending_a.next() else {
+                        break;
+                    };
+                    a = next_a;
+                    pending_b = range_b.clone();
+                }
+                let (region_a, region_b) = stack.pop()?;
+                if box_lower_bound(metric, &region_a, &region_b).is_some_and(|d| d > radius) {
+                    continue;
+                }
+                let split_a = self.split_region(&region_a);
+                let split_b = other.split_region(&region_b);
+                match (split_a, split_b) {
+                    (Some(parts), Some(_)) if region_a.len >= region_b.len => {
+                        stack.extend(parts.into_iter().flatten().map(|a| (a, region_b)));
+                    }
+                    (_, Some(parts)) => {
+                        stack.extend(parts.into_iter().flatten().map(|b| (region_a, b)));
+                    }
+                    (Some(parts), None) => {
+                        stack.extend(parts.into_iter().flatten().map(|a| (a, region_b)));
+                    }
+                    (None, None) => {
+                        pending_a = region_a.range();
+                        range_b = region_b.range();
+                        pending_b = 0..0;
+                    }
+                }
+            }
+        })
+    }
+}
+
+impl<C: Ord + Copy, V, const K: usize> TreeKD<C, V, K> {
+    /// Returns the whole tree and its bounding box.
+    fn root_region(&self) -> Option<Region<C, K>> {
+        let root = self.root()?;
+        let mut min = self.nodes[0].location;
+        let mut max = min;
+        for node in self.nodes.iter() {
+            for i in 0..K {
+                min[i] = min[i].min(node.location[i]);
+                max[i] = max[i].max(node.location[i]);
+            }
+        }
+        Some(Region {
+            part: Part::Subtree(root),
+            len: root.len,
+            min,
+            max,
+        })
+    }
+
+    /// Splits the region into the split node, the lesser and the greater subtree.
+    ///
+    /// Returns `None` if the region is a node or a leaf bucket.
+    fn split_region(&self, region: &Region<C, K>) -> Option<[Option<Region<C, K>>; 3]> {
+        let Part::Subtree(subtree) = region.part else {
+            return None;
+        };
+        let [lesser, greater] = self.children(subtree)?;
+        let coord_index = subtree.coord_index;
+        let split_value = self.split_value(subtree.index, coord_index);
+        let location = self.nodes[subtree.index].location;
+        let node = Region {
+            part: Part::Node(subtree.index),
+            len: 1,
+            min: location,
+            max: location,
+        };
+        let lesser = lesser.map(|lesser| {
+            let mut max = region.max;
+            max[coord_index] = max[coord_index].min(split_value);
+            Region {
+                part: Part::Subtree(lesser),
+                len: region.len / 2,
+                min: region.min,
+                max,
+            }
+        });
+        let greater = greater.map(|greater| {
+            let mut min = region.min;
+            min[coord_index] = min[coord_index].max(split_value);
+            Region {
+                part: Part::Subtree(greater),
+                len: region.len - region.len / 2 - 1,
+                min,
+                max: region.max,
+            }
+        });
+        Some([Some(node), lesser, greater])
+    }
+}
+
+impl<C, const K: usize> Region<C, K> {
+    fn range(&self) -> Range<usize> {
+        match self.part {
+            Part::Node(i) => i..i + 1,
+            Part::Subtree(subtree) => subtree.range(),
+        }
+    }
+}
+
+/// Returns the lower bound of the distance between the points in two boxes or `None` if the boxes
+/// overlap.
+fn box_lower_bound<C: Ord + Copy, M: Metric<C, K>, const K: usize>(
+    metric: &M,
+    a: &Region<C, K>,
+    b: &Region<C, K>,
+) -> Option<M::Distance> {
+    (0..K)
+        .filter_map(|axis| {
+            if a.max[axis] < b.min[axis] {
+                Some(metric.box_lower_bound(&a.min, &a.max, axis, b.min[axis]))
+            } else if b.max[axis] < a.min[axis] {
+                Some(metric.box_lower_bound(&a.min, &a.max, axis, b.max[axis]))
+            } else {
+                None
+            }
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EuclideanDistanceSquared;
+    use crate::Layout;
+    use crate::OrthogonalDistance;
+    use crate::Tree2D;
+    use crate::TreeOptions;
+    use crate::tree::testing::arbitrary_tree;
+    use alloc::string::String;
+    use arbtest::arbtest;
+
+    #[test]
+    fn join_within_radius_matches_brute_force() {
+        arbtest(|u| {
+            let a: Tree2D<i64, u32> = arbitrary_tree(u, 1000)?;
+            // The trees may have different value types.
+            let b: Tree2D<i64, String> = arbitrary_tree(u, 1000)?;
+            let radius = u.int_in_range(0..=300)?;
+            check_join(&a, &b, radius, &OrthogonalDistance);
+            check_join(&a, &b, radius * radius, &EuclideanDistanceSquared);
+            Ok(())
+        });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn join_within_radius_matches_brute_force_on_earth() {
+        use crate::EarthDistance;
+        use crate::tree::testing::arbitrary_geo_tree;
+        arbtest(|u| {
+            let a = arbitrary_geo_tree(u)?;
+            let b = arbitrary_geo_tree(u)?;
+            let radius = u.int_in_range(0..=5_000_000)?;
+            check_join(&a, &b, radius, &EarthDistance);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn join_within_radius_computes_few_distances() {
+        struct Counter(core::cell::Cell<usize>);
+
+        impl Metric<i64> for Counter {
+            type Distance = u64;
+
+            fn distance(&self, a: &[i64; 2], b: &[i64; 2]) -> u64 {
+                self.0.set(self.0.get() + 1);
+                OrthogonalDistance.distance(a, b)
+            }
+
+            fn axis_lower_bound(&self, query: &[i64; 2], axis: usize, split_value: i64) -> u64 {
+                Metric::<i64>::axis_lower_bound(&OrthogonalDistance, query, axis, split_value)
+            }
+        }
+
+        let mut nodes = Vec::new();
+        for x in 0..100 {
+            for y in 0..100 {
+                nodes.push(([x, y], ()));
+            }
+        }
+        for layout in [Layout::Explicit, Layout::Implicit, Layout::VanEmdeBoas] {
+            let options = TreeOptions::new().leaf_size(4).layout(layout);
+            let tree = Tree2D::from_nodes_with_options(nodes.clone(), options);
+            let metric = Counter(Default::default());
+            // Each node has at most 8 neighbours and itself.
+            assert_eq!(88_804, tree.join_within_radius(&tree, 1, &metric).count());
+            assert!(metric.0.get() < 1_000_000, "distances = {}", metric.0.get());
+        }
+    }
+
+    fn check_join<V: Default, W: Default, M: Metric<i64, 2, Distance = u64>>(
+        a: &Tree2D<i64, V>,
+        b: &Tree2D<i64, W>,
+        radius: u64,
+        metric: &M,
+    ) {
+        let mut expected = Vec::new();
+        for (i, (x, _)) in a.iter().enumerate() {
+            for (j, (y, _)) in b.iter().enumerate() {
+                let d = metric.distance(x, y);
+                if d <= radius {
+                    expected.push((i, j, d));
+                }
+            }
+        }
+        let mut actual: Vec<_> = a.join_within_radius(b, radius, metric).collect();
+        actual.sort_unstable();
+        assert_eq!(expected, actual);
+    }
+}
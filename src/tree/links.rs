@@ -0,0 +1,118 @@
+use alloc::vec::Vec;
+
+/// The index of the absent child.
+pub(super) const EMPTY: usize = 0;
+
+/// Unsigned integer that stores one-based node indices.
+pub(super) trait Index: Copy + Default + Eq {
+    fn from_usize(i: usize) -> Self;
+    fn to_usize(self) -> usize;
+}
+
+impl Index for u32 {
+    #[inline]
+    fn from_usize(i: usize) -> Self {
+        i as u32
+    }
+
+    #[inline]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+impl Index for u64 {
+    #[inline]
+    fn from_usize(i: usize) -> Self {
+        i as u64
+    }
+
+    #[inline]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+/// One-based indices of the children of the split node.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub(super) struct Link<I> {
+    pub(super) lesser_index: I,
+    pub(super) greater_index: I,
+}
+
+/// The children of the split nodes.
+///
+/// The indices are stored as `u32` unless the tree has more than `u32::MAX` nodes.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Links {
+    Narrow(Vec<Link<u32>>),
+    Wide(Vec<Link<u64>>),
+}
+
+impl Links {
+    /// Returns `true` if the indices of `num_nodes` nodes don't fit into `u32`.
+    pub(super) fn is_wide(num_nodes: usize) -> bool {
+        num_nodes as u64 > u64::from(u32::MAX)
+    }
+
+    #[cfg(feature = "std")]
+    /// Create links from the columns of lesser and greater indices.
+    ///
+    /// Returns `None` if any of the indices is out of bounds.
+    pub(super) fn from_columns(
+        num_nodes: usize,
+        lesser_indices: Vec<u64>,
+        greater_indices: Vec<u64>,
+    ) -> Option<Self> {
+        if lesser_indices
+            .iter()
+            .chain(greater_indices.iter())
+            .any(|i| *i > num_nodes as u64)
+        {
+            return None;
+        }
+        fn collect<I: Index>(lesser_indices: Vec<u64>, greater_indices: Vec<u64>) -> Vec<Link<I>> {
+            lesser_indices
+                .into_iter()
+                .zip(greater_indices)
+                .map(|(lesser_index, greater_index)| Link {
+                    lesser_index: I::from_usize(lesser_index as usize),
+                    greater_index: I::from_usize(greater_index as usize),
+                })
+                .collect()
+        }
+        Some(if Self::is_wide(num_nodes) {
+            Self::Wide(collect(lesser_indices, greater_indices))
+        } else {
+            Self::Narrow(collect(lesser_indices, greater_indices))
+        })
+    }
+
+    /// Returns the lesser and the greater index of the split node.
+    #[inline]
+    pub(super) fn get(&self, i: usize) -> [usize; 2] {
+        match self {
+            Self::Narrow(links) => [
+                links[i].lesser_index.to_usize(),
+                links[i].greater_index.to_usize(),
+            ],
+            Self::Wide(links) => [
+                links[i].lesser_index.to_usize(),
+                links[i].greater_index.to_usize(),
+            ],
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub(super) fn iter(&self) -> impl Iterator<Item = [usize; 2]> + Clone + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    #[cfg(any(test, feature = "std"))]
+    pub(super) fn len(&self) -> usize {
+        match self {
+            Self::Narrow(links) => links.len(),
+            Self::Wide(links) => links.len(),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use alloc::vec::Vec;
+
+use super::Scratch;
+use super::SearchStats;
+use super::TreeKD;
+use crate::R_AVG;
+use crate::earth_distance;
+use crate::to_f64;
+use crate::to_normal_vector;
+
+/// The components of unit vectors are stored as integers multiplied by this value.
+///
+/// The resulting precision is around 6 mm on the surface of the Earth.
+const SCALE: f64 = 1e9;
+
+/// Geographic tree that maps a location given by longitude and latitude in nanodegrees to
+/// a value `V`.
+///
+/// Unlike [`Tree2D`](crate::Tree2D) the locations are indexed as three-dimensional
+/// [n-vectors](https://en.wikipedia.org/wiki/N-vector), i.e. unit vectors that are normal to
+/// the surface of the Earth. Euclidean distance between n-vectors (chord length) increases
+/// monotonically with great-circle distance, hence there are no special cases near the
+/// antimeridian and the poles.
+/// The distances are computed using [`earth_distance`].
+///
+/// # References
+///
+/// - <https://en.wikipedia.org/wiki/K-d_tree>
+/// - <https://en.wikipedia.org/wiki/N-vector>
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Debug, PartialEq, Eq)]
+pub struct NVectorTree<V> {
+    tree: TreeKD<i64, ([i64; 2], V), 3>,
+}
+
+impl<V: Default> NVectorTree<V> {
+    /// Create a new tree from the given nodes.
+    ///
+    /// The first coordinate is longitude, the second coordinate is latitude.
+    /// Both are in nanodegrees.
+    ///
+    /// The nodes are converted to n-vectors and then stored in [`TreeKD`].
+    ///
+    /// The values are moved from the vector without copying.
+    pub fn from_nodes(nodes: Vec<([i64; 2], V)>) -> Self
+    where
+        V: Ord,
+    {
+        let nodes = nodes
+            .into_iter()
+            .map(|(location, value)| (to_vector(&location), (location, value)))
+            .collect();
+        Self {
+            tree: TreeKD::from_nodes(nodes),
+        }
+    }
+
+    /// Returns up to `max_neighbours` nodes within `max_distance` metres that are closest to
+    /// the `location`.
+    ///
+    /// The distance between nodes is computed using [`earth_distance`].
+    pub fn find_nearest(
+        &self,
+        location: &[i64; 2],
+        max_distance: u64,
+        max_neighbours: usize,
+    ) -> Vec<(u64, &[i64; 2], &V)> {
+        let vector = to_vector(location);
+        let mut stats = SearchStats::default();
+        let mut neighbours = Vec::new();
+        self.tree.find_nearest_impl(
+            &vector,
+            max_distance,
+            max_neighbours,
+            |_, (other, _)| Some(earth_distance(other, location)),
+            |axis, split_value| plane_distance(&vector, axis, split_value),
+            &mut stats,
+            &mut Scratch::new(),
+            |d, i| {
+                let (d, _, (location, value)) = self.tree.neighbour(d, i);
+                neighbours.push((d, location, value));
+            },
+        );
+        neighbours
+    }
+
+    /// Returns the node within `max_distance` metres that is closest to the `location`.
+    ///
+    /// This is the same as [`find_nearest`](Self::find_nearest) with `max_neighbours == 1` but
+    /// without memory allocations.
+    pub fn find_nearest_one(
+        &self,
+        location: &[i64; 2],
+        max_distance: u64,
+    ) -> Option<(u64, &[i64; 2], &V)> {
+        let vector = to_vector(location);
+        self.tree
+            .find_nearest_one_impl(
+                &vector,
+                max_distance,
+                &|_, (other, _)| earth_distance(other, location),
+                &|axis, split_value| plane_distance(&vector, axis, split_value),
+            )
+            .map(|(d, i)| {
+                let (d, _, (location, value)) = self.tree.neighbour(d, i);
+                (d, location, value)
+            })
+    }
+
+    /// Returns an iterator over nodes.
+    pub fn iter(&self) -> impl Iterator<Item = (&[i64; 2], &V)> {
+        self.tree
+            .iter()
+            .map(|(_, (location, value))| (location, value))
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Returns `true` if the tree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+}
+
+fn to_vector(location: &[i64; 2]) -> [i64; 3] {
+    to_normal_vector(&to_f64(location)).map(|x| (x * SCALE).round() as i64)
+}
+
+/// Returns the lower bound of the distance in metres between the point given by the `vector` and
+/// any point that lies on the plane `x[axis] = split_value` or on the other side of it.
+fn plane_distance(vector: &[i64; 3], axis: usize, split_value: i64) -> u64 {
+    // Each component of both vectors is rounded to the nearest integer.
+    let d = vector[axis].abs_diff(split_value).saturating_sub(1);
+    let chord = d as f64 / SCALE;
+    let angle = 2.0 * (chord * 0.5).min(1.0).asin();
+    // Compensate for rounding errors.
+    ((R_AVG * angle) as u64).saturating_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::testing::arbitrary_geo_location;
+    use arbtest::arbtest;
+
+    #[test]
+    fn find_nearest_matches_brute_force() {
+        arbtest(|u| {
+            let num_nodes = u.arbitrary_len::<([i64; 2], u32)>()?;
+            let mut nodes = Vec::with_capacity(num_nodes);
+            for _ in 0..num_nodes {
+                nodes.push((arbitrary_geo_location(u)?, u.arbitrary::<u32>()?));
+            }
+            let tree = NVectorTree::from_nodes(nodes);
+            let location = arbitrary_geo_location(u)?;
+            let max_neighbours = u.int_in_range(0..=20)?;
+            let max_distance = u.int_in_range(0..=10_000_000)?;
+            let actual: Vec<_> = tree
+                .find_nearest(&location, max_distance, max_neighbours)
+                .into_iter()
+                .map(|(d, ..)| d)
+                .collect();
+            let mut expected: Vec<_> = tree
+                .iter()
+                .map(|(other, _)| earth_distance(other, &location))
+                .filter(|d| *d <= max_distance)
+                .collect();
+            expected.sort_unstable();
+            let nearest = expected.first().copied();
+            expected.truncate(max_neighbours);
+            assert_eq!(expected, actual, "location = {location:?}, tree = {tree:?}");
+            assert_eq!(
+                nearest.as_ref(),
+                tree.find_nearest_one(&location, max_distance)
+                    .as_ref()
+                    .map(|(d, ..)| d)
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn find_nearest_across_antimeridian_and_pole() {
+        const DEGREE: i64 = 1_000_000_000;
+        let tree = NVectorTree::from_nodes(alloc::vec![
+            ([-180 * DEGREE + DEGREE / 1000, 0], "Fiji"),
+            ([179 * DEGREE, 0], "West"),
+            ([0, 90 * DEGREE - DEGREE / 100], "North"),
+            ([45 * DEGREE, 89 * DEGREE], "South"),
+        ]);
+        let (_, _, name) = tree
+            .find_nearest_one(&[180 * DEGREE - DEGREE / 1000, 0], u64::MAX)
+            .unwrap();
+        assert_eq!("Fiji", *name);
+        let (_, _, name) = tree
+            .find_nearest_one(&[180 * DEGREE, 90 * DEGREE - DEGREE / 100], u64::MAX)
+            .unwrap();
+        assert_eq!("North", *name);
+    }
+}
@@ -1,6 +1,11 @@
 use alloc::vec::Vec;
 use core::mem::size_of;
 
+/// The maximum number of values for which the memory is allocated before reading them.
+///
+/// The number of values comes from the stream and can't be trusted.
+const MAX_PREALLOCATED: usize = 4096;
+
 pub trait Read {
     fn read_u32(&mut self) -> std::io::Result<u32> {
         let mut buf = [0; 4];
@@ -8,9 +13,16 @@ pub trait Read {
         Ok(u32::from_le_bytes(buf))
     }
 
+    fn read_u64(&mut self) -> std::io::Result<u64> {
+        let mut buf = [0; 8];
+        self.read_bytes(&mut buf[..])?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
     fn read_bytes(&mut self, buf: &mut [u8]) -> std::io::Result<()>;
     fn read_sign_magnitude(&mut self, count: usize) -> std::io::Result<Vec<i64>>;
     fn read_magnitude_monotonic(&mut self, count: usize) -> std::io::Result<Vec<u32>>;
+    fn read_magnitude_monotonic_u64(&mut self, count: usize) -> std::io::Result<Vec<u64>>;
     fn read_magnitude(&mut self, count: usize) -> std::io::Result<Vec<u32>>;
 }
 
@@ -21,16 +33,27 @@ impl<R: std::io::Read> Read for R {
 
     fn read_sign_magnitude(&mut self, count: usize) -> std::io::Result<Vec<i64>> {
         const MAX_BYTES: usize = size_of::<u64>();
-        let mut values = Vec::with_capacity(count);
+        let mut values = Vec::with_capacity(count.min(MAX_PREALLOCATED));
         if count == 0 {
             return Ok(values);
         }
         let mut buf = [0_u8; MAX_BYTES];
         self.read_exact(&mut buf[..1])?;
         let num_bytes = buf[0];
-        if num_bytes == 0 || usize::from(num_bytes) > MAX_BYTES {
+        if usize::from(num_bytes) > MAX_BYTES {
             return Err(std::io::ErrorKind::InvalidData.into());
         }
+        if num_bytes == 0 {
+            // Only the signs are stored.
+            for _ in 0..count {
+                self.read_exact(&mut buf[..1])?;
+                if buf[0] > 1 {
+                    return Err(std::io::ErrorKind::InvalidData.into());
+                }
+                values.push(0);
+            }
+            return Ok(values);
+        }
         // Read magnitudes.
         for _ in 0..count {
             self.read_exact(&mut buf[..usize::from(num_bytes)])?;
@@ -56,7 +79,7 @@ impl<R: std::io::Read> Read for R {
 
     fn read_magnitude_monotonic(&mut self, count: usize) -> std::io::Result<Vec<u32>> {
         const MAX_BYTES: usize = size_of::<u32>();
-        let mut values = Vec::with_capacity(count);
+        let mut values = Vec::with_capacity(count.min(MAX_PREALLOCATED));
         if count == 0 {
             return Ok(values);
         }
@@ -68,6 +91,7 @@ impl<R: std::io::Read> Read for R {
         }
         if num_bytes == 0 {
             values.resize(count, 0_u32);
+            return Ok(values);
         }
         // Read magnitudes.
         let mut prev = 0_u32;
@@ -80,9 +104,36 @@ impl<R: std::io::Read> Read for R {
         Ok(values)
     }
 
+    fn read_magnitude_monotonic_u64(&mut self, count: usize) -> std::io::Result<Vec<u64>> {
+        const MAX_BYTES: usize = size_of::<u64>();
+        let mut values = Vec::with_capacity(count.min(MAX_PREALLOCATED));
+        if count == 0 {
+            return Ok(values);
+        }
+        let mut buf = [0_u8; MAX_BYTES];
+        self.read_exact(&mut buf[..1])?;
+        let num_bytes = buf[0];
+        if usize::from(num_bytes) > MAX_BYTES {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        if num_bytes == 0 {
+            values.resize(count, 0_u64);
+            return Ok(values);
+        }
+        // Read magnitudes.
+        let mut prev = 0_u64;
+        for _ in 0..count {
+            self.read_exact(&mut buf[..usize::from(num_bytes)])?;
+            let magnitude = u64::from_le_bytes(buf).wrapping_add(prev);
+            values.push(magnitude);
+            prev = magnitude;
+        }
+        Ok(values)
+    }
+
     fn read_magnitude(&mut self, count: usize) -> std::io::Result<Vec<u32>> {
         const MAX_BYTES: usize = size_of::<u32>();
-        let mut values = Vec::with_capacity(count);
+        let mut values = Vec::with_capacity(count.min(MAX_PREALLOCATED));
         if count == 0 {
             return Ok(values);
         }
@@ -94,6 +145,7 @@ impl<R: std::io::Read> Read for R {
         }
         if num_bytes == 0 {
             values.resize(count, 0_u32);
+            return Ok(values);
         }
         // Read magnitudes.
         for _ in 0..count {
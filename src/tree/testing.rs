@@ -0,0 +1,78 @@
+use alloc::vec::Vec;
+use arbitrary::Arbitrary;
+use arbitrary::Unstructured;
+
+use super::Layout;
+use super::TreeKD;
+use super::TreeOptions;
+
+/// Returns a tree with arbitrary nodes and options.
+///
+/// The coordinates are in the range `-max_coord..=max_coord`.
+pub(crate) fn arbitrary_tree<'a, V, const K: usize>(
+    u: &mut Unstructured<'a>,
+    max_coord: i64,
+) -> arbitrary::Result<TreeKD<i64, V, K>>
+where
+    V: Arbitrary<'a> + Ord + Default,
+{
+    let num_nodes = u.arbitrary_len::<([i64; K], V)>()?;
+    let mut nodes = Vec::with_capacity(num_nodes);
+    for _ in 0..num_nodes {
+        nodes.push((arbitrary_location(u, max_coord)?, u.arbitrary()?));
+    }
+    Ok(TreeKD::from_nodes_with_options(
+        nodes,
+        arbitrary_options(u)?,
+    ))
+}
+
+pub(crate) fn arbitrary_options(u: &mut Unstructured<'_>) -> arbitrary::Result<TreeOptions> {
+    let layout = *u.choose(&[Layout::Explicit, Layout::Implicit, Layout::VanEmdeBoas])?;
+    Ok(TreeOptions::new()
+        .leaf_size(u.int_in_range(1..=16)?)
+        .layout(layout))
+}
+
+pub(crate) fn arbitrary_location<const K: usize>(
+    u: &mut Unstructured<'_>,
+    max_coord: i64,
+) -> arbitrary::Result<[i64; K]> {
+    let mut location = [0; K];
+    for x in location.iter_mut() {
+        *x = u.int_in_range(-max_coord..=max_coord)?;
+    }
+    Ok(location)
+}
+
+/// Returns a tree with arbitrary nodes on the Earth and arbitrary options.
+#[cfg(feature = "std")]
+pub(crate) fn arbitrary_geo_tree(
+    u: &mut Unstructured<'_>,
+) -> arbitrary::Result<crate::Tree2D<i64, u32>> {
+    let num_nodes = u.arbitrary_len::<([i64; 2], u32)>()?;
+    let mut nodes = Vec::with_capacity(num_nodes);
+    for _ in 0..num_nodes {
+        nodes.push((arbitrary_geo_location(u)?, u.arbitrary()?));
+    }
+    Ok(TreeKD::from_nodes_with_options(
+        nodes,
+        arbitrary_options(u)?,
+    ))
+}
+
+/// Returns longitude and latitude in nanodegrees.
+#[cfg(feature = "std")]
+pub(crate) fn arbitrary_geo_location(u: &mut Unstructured<'_>) -> arbitrary::Result<[i64; 2]> {
+    let longitude = u.int_in_range(-180_000_000_000..=180_000_000_000)?;
+    let latitude = u.int_in_range(-90_000_000_000..=90_000_000_000)?;
+    Ok([longitude, latitude])
+}
+
+/// Shuffles the `items` using Fisher–Yates algorithm.
+pub(crate) fn shuffle<T>(u: &mut Unstructured<'_>, items: &mut [T]) -> arbitrary::Result<()> {
+    for i in (1..items.len()).rev() {
+        items.swap(i, u.int_in_range(0..=i)?);
+    }
+    Ok(())
+}
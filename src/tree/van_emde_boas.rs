@@ -0,0 +1,93 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::EMPTY;
+use super::Index;
+use super::Link;
+
+/// Returns the indices of the split nodes in van Emde Boas order.
+///
+/// The tree is split at half of its height into the top subtree and the bottom subtrees, and each
+/// of them is laid out recursively one after another. The split nodes are stored first, hence
+/// the links that point past them (to the leaf buckets) are ignored.
+///
+/// # References
+///
+/// - Prokop, H. (1999). Cache-oblivious algorithms. Master's thesis, MIT.
+pub(super) fn van_emde_boas_order<I: Index>(links: &[Link<I>]) -> Vec<usize> {
+    let mut order = Vec::with_capacity(links.len());
+    if !links.is_empty() {
+        let mut height = 0;
+        let mut roots = vec![0];
+        while !roots.is_empty() {
+            roots = children(links, &roots);
+            height += 1;
+        }
+        visit(links, 0, height, &mut order);
+    }
+    order
+}
+
+fn visit<I: Index>(links: &[Link<I>], root: usize, height: usize, order: &mut Vec<usize>) {
+    if height == 1 {
+        order.push(root);
+        return;
+    }
+    let top_height = height / 2;
+    visit(links, root, top_height, order);
+    let mut roots = vec![root];
+    for _ in 0..top_height {
+        roots = children(links, &roots);
+    }
+    for root in roots {
+        visit(links, root, height - top_height, order);
+    }
+}
+
+/// Returns the split nodes that are the children of the `parents` from left to right.
+fn children<I: Index>(links: &[Link<I>], parents: &[usize]) -> Vec<usize> {
+    parents
+        .iter()
+        .flat_map(|i| [links[*i].lesser_index, links[*i].greater_index])
+        .map(Index::to_usize)
+        .filter(|i| *i != EMPTY && *i - 1 < links.len())
+        .map(|i| i - 1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn van_emde_boas_order_works() {
+        // Perfect tree in breadth-first order; the leaves point to the buckets.
+        let links: Vec<_> = (0..15_u32)
+            .map(|i| Link {
+                lesser_index: 2 * i + 2,
+                greater_index: 2 * i + 3,
+            })
+            .collect();
+        assert_eq!(
+            vec![0, 1, 2, 3, 7, 8, 4, 9, 10, 5, 11, 12, 6, 13, 14],
+            van_emde_boas_order(&links)
+        );
+        // Incomplete tree.
+        let links = [
+            Link {
+                lesser_index: 2_u32,
+                greater_index: 3,
+            },
+            Link {
+                lesser_index: 4,
+                greater_index: 0,
+            },
+            Link {
+                lesser_index: 5,
+                greater_index: 6,
+            },
+            Link::default(),
+        ];
+        assert_eq!(vec![0, 1, 3, 2], van_emde_boas_order(&links));
+    }
+}
@@ -3,6 +3,10 @@ pub trait Write {
         self.write_bytes(&value.to_le_bytes()[..])
     }
 
+    fn write_u64(&mut self, value: u64) -> std::io::Result<()> {
+        self.write_bytes(&value.to_le_bytes()[..])
+    }
+
     fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()>;
 
     fn write_sign_magnitude(
@@ -15,6 +19,11 @@ pub trait Write {
         values: impl IntoIterator<Item = u32> + Clone,
     ) -> std::io::Result<()>;
 
+    fn write_magnitude_monotonic_u64(
+        &mut self,
+        values: impl IntoIterator<Item = u64> + Clone,
+    ) -> std::io::Result<()>;
+
     fn write_magnitude(
         &mut self,
         values: impl IntoIterator<Item = u32> + Clone,
@@ -110,6 +119,45 @@ impl<W: std::io::Write> Write for W {
         Ok(())
     }
 
+    fn write_magnitude_monotonic_u64(
+        &mut self,
+        values: impl IntoIterator<Item = u64> + Clone,
+    ) -> std::io::Result<()> {
+        let mut count = 0;
+        let num_zeros = {
+            let mut prev = 0_u64;
+            let mut min_clz = u64::BITS;
+            for v in values.clone().into_iter() {
+                let magnitude = v.wrapping_sub(prev);
+                let clz = magnitude.leading_zeros();
+                if clz < min_clz {
+                    min_clz = clz;
+                }
+                prev = v;
+                count += 1;
+            }
+            min_clz
+        };
+        if count == 0 {
+            return Ok(());
+        }
+        let num_bytes = (u64::BITS - num_zeros).div_ceil(u8::BITS);
+        self.write_all(&[num_bytes as u8])?;
+        if num_bytes == 0 {
+            return Ok(());
+        }
+        // Write magnitudes.
+        {
+            let mut prev = 0_u64;
+            for v in values.clone().into_iter() {
+                let magnitude = v.wrapping_sub(prev);
+                self.write_all(&magnitude.to_le_bytes()[..num_bytes as usize])?;
+                prev = v;
+            }
+        }
+        Ok(())
+    }
+
     fn write_magnitude(
         &mut self,
         values: impl IntoIterator<Item = u32> + Clone,